@@ -133,7 +133,7 @@ fn main() {
                     routing_key: "unroutable-routing-key-for-tests".into(),
                     redelivered: false,
                     properties: BasicProperties::default().with_priority(42),
-                    data: payload.to_vec(),
+                    data: payload.to_vec().into(),
                     acker: Default::default(),
                 },
                 reply_code: 312,
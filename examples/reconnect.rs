@@ -1,28 +1,26 @@
+use async_reactor_trait::AsyncIo;
 use futures_lite::stream::StreamExt;
 use lapin::{
-    options::*, publisher_confirm::Confirmation, types::FieldTable, BasicProperties, Connection,
-    ConnectionProperties, Result,
+    options::*, types::FieldTable, BasicProperties, Connection, ConnectionProperties,
+    RecoveryConfig, Result,
 };
+use reactor_trait::Reactor;
+use std::time::Duration;
 use tracing::info;
 
-fn retry_rabbit_stuff(addr: String) {
-    std::thread::sleep(std::time::Duration::from_millis(2000));
-    tracing::debug!("Reconnecting to rabbitmq");
-    try_rabbit_stuff(addr);
-}
-
-fn try_rabbit_stuff(addr: String) {
-    async_global_executor::spawn(async move {
-        if let Err(err) = rabbit_stuff(addr.clone()).await {
-            tracing::error!("Error: {}", err);
-            retry_rabbit_stuff(addr);
-        }
-    })
-    .detach();
-}
-
 async fn rabbit_stuff(addr: String) -> Result<()> {
-    let conn = Connection::connect(&addr, ConnectionProperties::default()).await?;
+    // With a RecoveryConfig, the connection will automatically reconnect when the socket dies,
+    // and restore channels, queues, bindings and consumers.
+    let conn = Connection::connect(
+        &addr,
+        ConnectionProperties::default().with_recovery_config(RecoveryConfig {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            ..RecoveryConfig::default()
+        }),
+    )
+    .await?;
+    conn.on_error(|err| tracing::error!("Error: {}", err));
 
     info!("CONNECTED");
 
@@ -49,9 +47,12 @@ async fn rabbit_stuff(addr: String) -> Result<()> {
         .await?;
     async_global_executor::spawn(async move {
         info!("will consume");
+        // This loop keeps running across reconnections
         while let Some(delivery) = consumer.next().await {
             let delivery = delivery.expect("error in consumer");
-            delivery.ack(BasicAckOptions::default()).await.expect("ack");
+            if let Err(err) = delivery.ack(BasicAckOptions::default()).await {
+                tracing::warn!("Failed to ack (connection lost?): {}", err);
+            }
         }
     })
     .detach();
@@ -59,17 +60,23 @@ async fn rabbit_stuff(addr: String) -> Result<()> {
     let payload = b"Hello world!";
 
     loop {
-        let confirm = channel_a
-            .basic_publish(
-                "",
-                "hello",
-                BasicPublishOptions::default(),
-                payload.to_vec(),
-                BasicProperties::default(),
-            )
-            .await?
-            .await?;
-        assert_eq!(confirm, Confirmation::NotRequested);
+        let res = async {
+            channel_a
+                .basic_publish(
+                    "",
+                    "hello",
+                    BasicPublishOptions::default(),
+                    payload.to_vec(),
+                    BasicProperties::default(),
+                )
+                .await?
+                .await
+        }
+        .await;
+        if let Err(err) = res {
+            tracing::warn!(status=?conn.status().state(), "Failed to publish: {}", err);
+            AsyncIo.sleep(Duration::from_secs(1)).await;
+        }
     }
 }
 
@@ -82,7 +89,5 @@ fn main() -> Result<()> {
 
     let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
 
-    try_rabbit_stuff(addr);
-
-    async_global_executor::block_on(futures_lite::future::pending())
+    async_global_executor::block_on(rabbit_stuff(addr))
 }
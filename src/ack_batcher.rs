@@ -0,0 +1,151 @@
+use crate::{channel_status::ChannelStatus, types::DeliveryTag, Error, PromiseResolver};
+use parking_lot::Mutex;
+use std::{collections::BTreeMap, fmt, sync::Arc, time::Duration};
+
+/// How the acks sent through [`Acker::ack`] get coalesced on a channel, see
+/// [`Channel::set_ack_batching`].
+///
+/// Pending acks get flushed as a single `basic.ack` with `multiple` set, covering all the
+/// deliveries up to the last one of the contiguous range of acked deliveries, once there are
+/// `max_messages` of them. `max_delay` after the first pending ack, everything gets flushed,
+/// acking separately the deliveries which are still waiting for an earlier one.
+///
+/// [`Acker::ack`]: ./struct.Acker.html#method.ack
+/// [`Channel::set_ack_batching`]: ./struct.Channel.html#method.set_ack_batching
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AckBatching {
+    /// How many acks can be pending before we flush them
+    pub max_messages: usize,
+    /// How long an ack can be pending before we flush it
+    pub max_delay: Duration,
+}
+
+impl Default for AckBatching {
+    fn default() -> Self {
+        Self {
+            max_messages: 64,
+            max_delay: Duration::from_millis(10),
+        }
+    }
+}
+
+impl AckBatching {
+    /// Flush the pending acks once there are `max_messages` of them
+    pub fn with_max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = max_messages;
+        self
+    }
+
+    /// Flush the pending acks at most `max_delay` after the first of them
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+}
+
+/// The acks waiting to be flushed on a channel
+#[derive(Clone, Default)]
+pub(crate) struct AckBatcher(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    batching: Option<AckBatching>,
+    pending: BTreeMap<DeliveryTag, PromiseResolver<()>>,
+    flush_scheduled: bool,
+}
+
+impl AckBatcher {
+    pub(crate) fn set_batching(&self, batching: AckBatching) {
+        self.0.lock().batching = Some(batching);
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.0.lock().batching.is_some()
+    }
+
+    /// Add a pending ack, returning after how long to flush if no flush is scheduled yet
+    pub(crate) fn push(
+        &self,
+        delivery_tag: DeliveryTag,
+        resolver: PromiseResolver<()>,
+    ) -> Option<Duration> {
+        let mut inner = self.0.lock();
+        inner.pending.insert(delivery_tag, resolver);
+        if inner.flush_scheduled {
+            return None;
+        }
+        inner.flush_scheduled = true;
+        inner.batching.map(|batching| batching.max_delay)
+    }
+
+    pub(crate) fn full(&self) -> bool {
+        let inner = self.0.lock();
+        inner.batching.map_or(false, |batching| {
+            inner.pending.len() >= batching.max_messages
+        })
+    }
+
+    /// Take the pending acks which can be flushed together: the ones for the first unacked
+    /// deliveries, up to the first one which wasn't acked yet. Returns the last of them.
+    pub(crate) fn take_contiguous(
+        &self,
+        status: &ChannelStatus,
+    ) -> Option<(DeliveryTag, Vec<PromiseResolver<()>>)> {
+        let mut inner = self.0.lock();
+        let last = status.last_contiguous_delivery(|tag| inner.pending.contains_key(&tag))?;
+        let rest = inner.pending.split_off(&(last + 1));
+        let covered = std::mem::replace(&mut inner.pending, rest);
+        Some((
+            last,
+            covered.into_iter().map(|(_, resolver)| resolver).collect(),
+        ))
+    }
+
+    /// Take the pending acks for the deliveries up to `delivery_tag`, or all of them if it's 0
+    pub(crate) fn take_up_to(
+        &self,
+        delivery_tag: DeliveryTag,
+    ) -> Vec<(DeliveryTag, PromiseResolver<()>)> {
+        let mut inner = self.0.lock();
+        let rest = if delivery_tag == 0 {
+            BTreeMap::default()
+        } else {
+            inner.pending.split_off(&(delivery_tag + 1))
+        };
+        std::mem::replace(&mut inner.pending, rest)
+            .into_iter()
+            .collect()
+    }
+
+    /// Take all the pending acks, the scheduled flush being done
+    pub(crate) fn take_all(&self) -> Vec<(DeliveryTag, PromiseResolver<()>)> {
+        let mut inner = self.0.lock();
+        inner.flush_scheduled = false;
+        std::mem::take(&mut inner.pending).into_iter().collect()
+    }
+
+    /// The pending acks can no longer be sent, their delivery tags belonging to a previous channel.
+    /// The next ack schedules a new flush.
+    pub(crate) fn fail(&self, error: Error) {
+        let pending = {
+            let mut inner = self.0.lock();
+            inner.flush_scheduled = false;
+            std::mem::take(&mut inner.pending)
+        };
+        for (_, resolver) in pending {
+            resolver.swear(Err(error.clone()));
+        }
+    }
+}
+
+impl fmt::Debug for AckBatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("AckBatcher");
+        if let Some(inner) = self.0.try_lock() {
+            debug
+                .field("batching", &inner.batching)
+                .field("pending", &inner.pending.keys().collect::<Vec<_>>());
+        }
+        debug.finish()
+    }
+}
@@ -4,6 +4,7 @@ use crate::{
     publisher_confirm::{Confirmation, PublisherConfirm},
     returned_messages::ReturnedMessages,
     types::DeliveryTag,
+    wakers::Wakers,
     Error, Promise, Result,
 };
 use parking_lot::Mutex;
@@ -11,6 +12,7 @@ use std::{
     collections::{HashMap, HashSet},
     fmt,
     sync::Arc,
+    task::Waker,
 };
 use tracing::trace;
 
@@ -61,9 +63,24 @@ impl Acknowledgements {
         self.0.lock().complete_pending_before(delivery_tag, false)
     }
 
+    pub(crate) fn pending(&self) -> Vec<DeliveryTag> {
+        let mut pending = self.0.lock().pending.keys().copied().collect::<Vec<_>>();
+        pending.sort_unstable();
+        pending
+    }
+
     pub(crate) fn on_channel_error(&self, error: Error) {
         self.0.lock().on_channel_error(error);
     }
+
+    /// Wake `waker` up once no publish is waiting for its confirmation anymore
+    pub(crate) fn register_drain_waker(&self, waker: &Waker) {
+        self.0.lock().drain_wakers.register(waker);
+    }
+
+    pub(crate) fn reset(&self) {
+        self.0.lock().reset();
+    }
 }
 
 impl fmt::Debug for Acknowledgements {
@@ -85,6 +102,7 @@ struct Inner {
     last: Option<(DeliveryTag, Promise<Confirmation>)>,
     pending: HashMap<DeliveryTag, ConfirmationBroadcaster>,
     returned_messages: ReturnedMessages,
+    drain_wakers: Wakers,
 }
 
 impl Inner {
@@ -95,6 +113,7 @@ impl Inner {
             last: None,
             pending: HashMap::default(),
             returned_messages,
+            drain_wakers: Wakers::default(),
         }
     }
 
@@ -121,6 +140,9 @@ impl Inner {
         } else {
             Confirmation::Nack(returned_message)
         }));
+        if self.pending.is_empty() {
+            self.drain_wakers.wake();
+        }
     }
 
     fn drop_all(&mut self, success: bool) {
@@ -174,5 +196,12 @@ impl Inner {
         for (_, resolver) in self.pending.drain() {
             resolver.swear(Err(error.clone()));
         }
+        self.drain_wakers.wake();
+    }
+
+    fn reset(&mut self) {
+        // The server starts numbering deliveries from 1 again on a reopened channel
+        self.delivery_tag = IdSequence::new(false);
+        self.last = None;
     }
 }
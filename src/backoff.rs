@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+/// The exponentially growing delay to wait before the retry number `retry` (starting at 0),
+/// starting at `initial_delay` and multiplied by `multiplier` after each failure, up to
+/// `max_delay`.
+pub(crate) fn delay(
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: u32,
+    retry: usize,
+) -> Duration {
+    let mut delay = initial_delay;
+    for _ in 0..retry {
+        if delay >= max_delay {
+            break;
+        }
+        delay = delay.checked_mul(multiplier).unwrap_or(max_delay);
+    }
+    delay.min(max_delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_delay() {
+        let delay = |retry| delay(Duration::from_millis(100), Duration::from_secs(1), 3, retry);
+        assert_eq!(delay(0), Duration::from_millis(100));
+        assert_eq!(delay(1), Duration::from_millis(300));
+        assert_eq!(delay(2), Duration::from_millis(900));
+        assert_eq!(delay(3), Duration::from_secs(1));
+        assert_eq!(delay(usize::MAX), Duration::from_secs(1));
+    }
+}
@@ -5,6 +5,7 @@ use crate::{
     types::{PayloadSize, ShortString},
     BasicProperties, PromiseResolver,
 };
+use bytes::BytesMut;
 use parking_lot::Mutex;
 use std::{fmt, sync::Arc};
 
@@ -70,6 +71,7 @@ impl Inner {
             queue,
             options,
             message,
+            payload: BytesMut::default(),
             resolver,
         });
     }
@@ -85,7 +87,9 @@ impl Inner {
 
     fn handle_body_frame(&mut self, remaining_size: PayloadSize, payload: Vec<u8>) {
         if let Some(inner) = self.0.as_mut() {
-            inner.message.receive_content(payload);
+            inner
+                .message
+                .receive_content(payload, remaining_size, &mut inner.payload);
         }
         if remaining_size == 0 {
             self.new_delivery_complete();
@@ -103,5 +107,6 @@ struct InnerData {
     queue: ShortString,
     options: BasicGetOptions,
     message: BasicGetMessage,
+    payload: BytesMut,
     resolver: PromiseResolver<Option<BasicGetMessage>>,
 }
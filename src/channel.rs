@@ -1,6 +1,6 @@
 use crate::{
+    ack_batcher::{AckBatcher, AckBatching},
     acknowledgement::Acknowledgements,
-    auth::Credentials,
     basic_get_delivery::BasicGetDelivery,
     channel_closer::ChannelCloser,
     channel_receiver_state::DeliveryCause,
@@ -8,27 +8,42 @@ use crate::{
     connection_closer::ConnectionCloser,
     connection_status::{ConnectionState, ConnectionStep},
     consumer::Consumer,
+    consumer_buffer::ConsumerBuffer,
     consumers::Consumers,
+    events::Event,
     frames::{ExpectedReply, Frames},
     internal_rpc::InternalRPCHandle,
-    message::{BasicGetMessage, BasicReturnMessage, Delivery},
+    message::{BasicGetMessage, BasicReturnMessage, BatchMessage, Delivery},
     protocol::{self, AMQPClass, AMQPError, AMQPHardError},
-    publisher_confirm::PublisherConfirm,
+    publisher_confirm::{BatchConfirm, PublisherConfirm},
     queue::Queue,
     registry::Registry,
-    returned_messages::ReturnedMessages,
+    returned_messages::{ReturnedMessageStream, ReturnedMessages},
+    sasl::SaslSession,
     socket_state::SocketStateHandle,
+    streaming::StreamingConsumer,
     topology::RestoredChannel,
     topology_internal::ChannelDefinitionInternal,
     types::*,
     BasicProperties, Configuration, Connection, ConnectionStatus, Error, ExchangeKind, Promise,
-    PromiseResolver, Result,
+    PromiseResolver, PublishBackpressure, Result, RetryPolicy,
 };
 use amq_protocol::frame::{AMQPContentHeader, AMQPFrame};
+use bytes::Bytes;
 use executor_trait::FullExecutor;
+use futures_lite::future;
+use reactor_trait::Reactor;
 use serde::{Deserialize, Serialize};
-use std::{convert::TryFrom, fmt, sync::Arc};
-use tracing::{error, info, level_enabled, trace, Level};
+use std::{
+    convert::TryFrom,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tracing::{error, info, level_enabled, trace, warn, Level};
 
 /// Main entry point for most AMQP operations.
 ///
@@ -48,6 +63,7 @@ pub struct Channel {
     global_registry: Registry,
     local_registry: Registry,
     acknowledgements: Acknowledgements,
+    ack_batcher: AckBatcher,
     consumers: Consumers,
     basic_get_delivery: BasicGetDelivery,
     returned_messages: ReturnedMessages,
@@ -55,6 +71,7 @@ pub struct Channel {
     internal_rpc: InternalRPCHandle,
     frames: Frames,
     executor: Arc<dyn FullExecutor + Send + Sync>,
+    reactor: Arc<dyn Reactor + Send + Sync>,
     channel_closer: Option<Arc<ChannelCloser>>,
     connection_closer: Option<Arc<ConnectionCloser>>,
 }
@@ -73,6 +90,7 @@ impl fmt::Debug for Channel {
             .field("status", &self.status)
             .field("connection_status", &self.connection_status)
             .field("acknowledgements", &self.acknowledgements)
+            .field("ack_batcher", &self.ack_batcher)
             .field("consumers", &self.consumers)
             .field("basic_get_delivery", &self.basic_get_delivery)
             .field("returned_messages", &self.returned_messages)
@@ -82,6 +100,7 @@ impl fmt::Debug for Channel {
 }
 
 impl Channel {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         channel_id: ChannelId,
         configuration: Configuration,
@@ -91,9 +110,10 @@ impl Channel {
         internal_rpc: InternalRPCHandle,
         frames: Frames,
         executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn Reactor + Send + Sync>,
         connection_closer: Option<Arc<ConnectionCloser>>,
     ) -> Channel {
-        let returned_messages = ReturnedMessages::default();
+        let returned_messages = ReturnedMessages::new(executor.clone());
         let status = ChannelStatus::default();
         let channel_closer = if channel_id == 0 {
             None
@@ -112,6 +132,7 @@ impl Channel {
             global_registry,
             local_registry: Registry::default(),
             acknowledgements: Acknowledgements::new(channel_id, returned_messages.clone()),
+            ack_batcher: AckBatcher::default(),
             consumers: Consumers::default(),
             basic_get_delivery: BasicGetDelivery::default(),
             returned_messages,
@@ -119,6 +140,7 @@ impl Channel {
             internal_rpc,
             frames,
             executor,
+            reactor,
             channel_closer,
             connection_closer,
         }
@@ -129,7 +151,18 @@ impl Channel {
     }
 
     pub(crate) fn reset(&self) {
-        // FIXME
+        if self.id != 0 {
+            self.set_state(ChannelState::Initial);
+        }
+        self.status.reset();
+        self.acknowledgements.reset();
+    }
+
+    pub(crate) fn set_recovering(&self, error: Error) {
+        self.reset();
+        self.ack_batcher.fail(error.clone());
+        self.error_publisher_confirms(error.clone());
+        self.consumers.invalidate_deliveries(error);
     }
 
     pub(crate) async fn restore(
@@ -178,6 +211,7 @@ impl Channel {
                     consumer.options,
                     consumer.arguments.clone(),
                     original,
+                    false,
                 )
                 .await?,
             );
@@ -196,6 +230,80 @@ impl Channel {
         Ok(())
     }
 
+    /// Resubscribe `consumer`, which got cancelled by the server, in the background
+    fn resubscribe(&self, consumer: Consumer, policy: RetryPolicy) {
+        let channel = self.clone();
+        self.executor.spawn(Box::pin(async move {
+            channel.do_resubscribe(consumer, policy).await;
+        }));
+    }
+
+    async fn do_resubscribe(&self, consumer: Consumer, policy: RetryPolicy) {
+        let consumer_tag = consumer.tag();
+        let mut attempt = 0;
+        loop {
+            self.reactor.sleep(policy.delay(attempt)).await;
+            // The consumer got cancelled on our side or restored by a recovery in the meantime
+            if !consumer.resubscribing() {
+                return;
+            }
+            trace!(channel=%self.id, %consumer_tag, attempt, "Resubscribing consumer");
+            match self.try_resubscribe(&consumer).await {
+                Ok(()) => return,
+                Err(error) if attempt < policy.max_retries => {
+                    warn!(channel=%self.id, %consumer_tag, %error, "Failed to resubscribe consumer");
+                    attempt += 1;
+                }
+                Err(error) => {
+                    error!(channel=%self.id, %consumer_tag, %error, "Giving up on resubscribing consumer");
+                    consumer.notify_error(error);
+                    self.consumers.deregister(consumer_tag.as_str());
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn try_resubscribe(&self, consumer: &Consumer) -> Result<()> {
+        let queue = consumer.queue();
+        // The queue may have been deleted, declare it again if it's part of our topology
+        if let Some(definition) = self
+            .local_registry
+            .queue(queue.as_str())
+            .or_else(|| self.global_registry.queue(queue.as_str()))
+        {
+            if definition.is_declared() {
+                self.queue_declare(
+                    queue.as_str(),
+                    definition.options.unwrap_or_default(),
+                    definition.arguments.clone().unwrap_or_default(),
+                )
+                .await?;
+            }
+            for binding in &definition.bindings {
+                self.queue_bind(
+                    queue.as_str(),
+                    binding.source.as_str(),
+                    binding.routing_key.as_str(),
+                    QueueBindOptions::default(),
+                    binding.arguments.clone(),
+                )
+                .await?;
+            }
+        }
+        self.do_basic_consume(
+            queue.as_str(),
+            consumer.tag().as_str(),
+            consumer.options(),
+            consumer.arguments(),
+            Some(consumer.clone()),
+            false,
+        )
+        .await
+        // The user still holds the handles given out when first consuming
+        .map(Consumer::release)
+    }
+
     fn set_closing(&self, error: Option<Error>) {
         self.set_state(ChannelState::Closing);
         if let Some(error) = error {
@@ -208,6 +316,14 @@ impl Channel {
 
     fn set_closed(&self, error: Error) {
         self.set_state(ChannelState::Closed);
+        // Only tell about the close once the close-ok went through, in either direction
+        if let Some((reply_code, reply_text)) = self.status.take_close_reply() {
+            self.connection_status.emit(Event::ChannelClosed {
+                channel_id: self.id,
+                reply_code,
+                reply_text,
+            });
+        }
         self.error_publisher_confirms(error.clone());
         self.cancel_consumers();
         self.internal_rpc.remove_channel(self.id, error);
@@ -236,10 +352,73 @@ impl Channel {
         self.status.set_state(state);
     }
 
+    /// Cancel all our consumers, returning the ones we couldn't cancel before `deadline`
+    pub(crate) async fn cancel_all_consumers(&self, deadline: Instant) -> Vec<ShortString> {
+        let mut uncancelled = Vec::new();
+        for consumer_tag in self.consumers.tags() {
+            let cancel = self.basic_cancel(consumer_tag.as_str(), BasicCancelOptions::default());
+            match self.until(deadline, cancel).await {
+                Some(Ok(())) => {}
+                Some(Err(error)) => {
+                    warn!(channel=%self.id, %consumer_tag, %error, "Failed to cancel consumer");
+                    uncancelled.push(consumer_tag);
+                }
+                None => {
+                    warn!(channel=%self.id, %consumer_tag, "Timed out cancelling consumer");
+                    uncancelled.push(consumer_tag);
+                }
+            }
+        }
+        uncancelled
+    }
+
+    /// Wait until all the deliveries we received are acked and all our publishes are confirmed,
+    /// or until `deadline`
+    pub(crate) async fn wait_drained(&self, deadline: Instant) {
+        self.until(
+            deadline,
+            Drained {
+                status: self.status.clone(),
+                acknowledgements: self.acknowledgements.clone(),
+            },
+        )
+        .await;
+    }
+
+    pub(crate) fn unacked_deliveries(&self) -> Vec<DeliveryTag> {
+        self.status.unacked_deliveries()
+    }
+
+    pub(crate) fn unconfirmed_publishes(&self) -> Vec<DeliveryTag> {
+        self.acknowledgements.pending()
+    }
+
+    // Run fut to completion unless deadline passes first
+    pub(crate) async fn until<T, F: Future<Output = T>>(
+        &self,
+        deadline: Instant,
+        fut: F,
+    ) -> Option<T> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        future::or(async { Some(fut.await) }, async {
+            self.reactor.sleep(timeout).await;
+            None
+        })
+        .await
+    }
+
     pub fn id(&self) -> ChannelId {
         self.id
     }
 
+    pub(crate) fn executor(&self) -> &Arc<dyn FullExecutor + Send + Sync> {
+        &self.executor
+    }
+
+    pub(crate) fn reactor(&self) -> &Arc<dyn Reactor + Send + Sync> {
+        &self.reactor
+    }
+
     pub(crate) fn clone_internal(&self) -> Self {
         Self {
             id: self.id,
@@ -249,6 +428,7 @@ impl Channel {
             global_registry: self.global_registry.clone(),
             local_registry: self.local_registry.clone(),
             acknowledgements: self.acknowledgements.clone(),
+            ack_batcher: self.ack_batcher.clone(),
             consumers: self.consumers.clone(),
             basic_get_delivery: self.basic_get_delivery.clone(),
             returned_messages: self.returned_messages.clone(),
@@ -256,6 +436,7 @@ impl Channel {
             internal_rpc: self.internal_rpc.clone(),
             frames: self.frames.clone(),
             executor: self.executor.clone(),
+            reactor: self.reactor.clone(),
             channel_closer: None,
             connection_closer: self.connection_closer.clone(),
         }
@@ -299,7 +480,42 @@ impl Channel {
         options: BasicConsumeOptions,
         arguments: FieldTable,
     ) -> Result<Consumer> {
-        self.do_basic_consume(queue, consumer_tag, options, arguments, None)
+        self.do_basic_consume(queue, consumer_tag, options, arguments, None, false)
+            .await
+    }
+
+    /// Like [`basic_consume`], but hand out each delivery as soon as its content header got
+    /// received, streaming its body afterwards. See [`StreamingConsumer`].
+    ///
+    /// [`basic_consume`]: #method.basic_consume
+    /// [`StreamingConsumer`]: ./struct.StreamingConsumer.html
+    pub async fn basic_consume_streaming(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+        options: BasicConsumeOptions,
+        arguments: FieldTable,
+    ) -> Result<StreamingConsumer> {
+        self.do_basic_consume(queue, consumer_tag, options, arguments, None, true)
+            .await
+            .map(StreamingConsumer::new)
+    }
+
+    /// Publish a message.
+    ///
+    /// The payload can be sent without being copied only when it fits in a single frame and isn't
+    /// referenced anywhere else. Otherwise, each body frame gets its own copy of its part of the
+    /// payload, as frames own their content.
+    pub async fn basic_publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: impl Into<Bytes>,
+        properties: BasicProperties,
+    ) -> Result<PublisherConfirm> {
+        self.wait_for_publish_permission().await?;
+        self.do_basic_publish(exchange, routing_key, options, payload, properties)
             .await
     }
 
@@ -337,6 +553,167 @@ impl Channel {
         Ok(self.returned_messages.drain())
     }
 
+    /// Call `handler` with each message returned by the server, whether publisher confirms are
+    /// enabled or not.
+    ///
+    /// The handler runs on the executor, getting the messages in the order they were returned.
+    ///
+    /// Returned messages which are passed to a handler or a stream from [`returned_messages`]
+    /// are no longer kept for [`wait_for_confirms`] when publisher confirms are disabled.
+    ///
+    /// [`returned_messages`]: ./struct.Channel.html#method.returned_messages
+    /// [`wait_for_confirms`]: ./struct.Channel.html#method.wait_for_confirms
+    pub fn on_return<F: FnMut(BasicReturnMessage) + Send + 'static>(&self, handler: F) {
+        self.returned_messages.set_handler(handler);
+    }
+
+    /// Coalesce the acks sent through [`Acker::ack`] on this channel, according to `batching`.
+    ///
+    /// Instead of sending a `basic.ack` for each delivery, we wait for the acks of the following
+    /// ones and ack them all at once with `multiple` set, which halves the number of frames
+    /// exchanged at high delivery rates. Each ack still only resolves once the frame covering it
+    /// got sent. Acks sent directly using [`basic_ack`] aren't coalesced and must not overlap
+    /// with the ones sent through [`Acker::ack`].
+    ///
+    /// [`Acker::ack`]: ./struct.Acker.html#method.ack
+    /// [`basic_ack`]: #method.basic_ack
+    pub fn set_ack_batching(&self, batching: AckBatching) {
+        self.ack_batcher.set_batching(batching);
+    }
+
+    /// Ack a delivery for an [`Acker`], coalescing it with others if enabled
+    ///
+    /// [`Acker`]: ./struct.Acker.html
+    pub(crate) async fn ack_delivery(
+        &self,
+        delivery_tag: DeliveryTag,
+        options: BasicAckOptions,
+    ) -> Result<()> {
+        if options.multiple {
+            // This also covers the pending acks for the previous deliveries
+            let covered = self.ack_batcher.take_up_to(delivery_tag);
+            let res = self.basic_ack(delivery_tag, options).await;
+            for (_, resolver) in covered {
+                resolver.swear(res.clone());
+            }
+            return res;
+        }
+        if !self.ack_batcher.enabled() {
+            return self.basic_ack(delivery_tag, options).await;
+        }
+        let (promise, resolver) = Promise::new();
+        if let Some(delay) = self.ack_batcher.push(delivery_tag, resolver) {
+            let channel = self.clone();
+            self.executor.spawn(Box::pin(async move {
+                channel.reactor.sleep(delay).await;
+                channel.flush_acks(true).await;
+            }));
+        }
+        if self.ack_batcher.full() {
+            self.flush_acks(false).await;
+        }
+        promise.await
+    }
+
+    /// Nack a delivery for an [`Acker`], flushing first the pending acks it would cover
+    ///
+    /// [`Acker`]: ./struct.Acker.html
+    pub(crate) async fn nack_delivery(
+        &self,
+        delivery_tag: DeliveryTag,
+        options: BasicNackOptions,
+    ) -> Result<()> {
+        if options.multiple {
+            for (delivery_tag, resolver) in self.ack_batcher.take_up_to(delivery_tag) {
+                resolver.swear(
+                    self.basic_ack(delivery_tag, BasicAckOptions::default())
+                        .await,
+                );
+            }
+        }
+        self.basic_nack(delivery_tag, options).await
+    }
+
+    /// Send the pending acks which can be coalesced, or all of them if `all` is set
+    async fn flush_acks(&self, all: bool) {
+        if let Some((delivery_tag, resolvers)) = self.ack_batcher.take_contiguous(&self.status) {
+            trace!(channel=%self.id, delivery_tag, count=resolvers.len(), "Flushing coalesced acks");
+            let res = self
+                .basic_ack(
+                    delivery_tag,
+                    BasicAckOptions {
+                        multiple: resolvers.len() > 1,
+                    },
+                )
+                .await;
+            for resolver in resolvers {
+                resolver.swear(res.clone());
+            }
+        }
+        if all {
+            for (delivery_tag, resolver) in self.ack_batcher.take_all() {
+                resolver.swear(
+                    self.basic_ack(delivery_tag, BasicAckOptions::default())
+                        .await,
+                );
+            }
+        }
+    }
+
+    /// A Stream of the messages returned by the server, whether publisher confirms are enabled
+    /// or not.
+    ///
+    /// Only the messages returned after the subscription are received, see [`on_return`].
+    ///
+    /// [`on_return`]: ./struct.Channel.html#method.on_return
+    pub fn returned_messages(&self) -> ReturnedMessageStream {
+        self.returned_messages.subscribe()
+    }
+
+    /// Publish a whole batch of messages at once.
+    ///
+    /// All the frames are queued in one go instead of going through one
+    /// [`basic_publish`] call per message. The returned [`BatchConfirm`] resolves to the
+    /// confirmation of each message, in order, once the server confirmed all of them. A message
+    /// whose confirmation got lost, e.g. because of a channel error, gets the corresponding error
+    /// without affecting the outcome of the other ones.
+    ///
+    /// [`basic_publish`]: ./struct.Channel.html#method.basic_publish
+    /// [`BatchConfirm`]: ./publisher_confirm/struct.BatchConfirm.html
+    pub async fn basic_publish_batch<I: IntoIterator<Item = BatchMessage>>(
+        &self,
+        messages: I,
+    ) -> Result<BatchConfirm> {
+        if !self.status.connected() {
+            return Err(Error::InvalidChannelState(self.status.state()));
+        }
+        self.wait_for_publish_permission().await?;
+
+        let mut frames = Vec::new();
+        let mut confirms = Vec::new();
+        for message in messages {
+            let confirm = self
+                .before_basic_publish()
+                .unwrap_or_else(|| PublisherConfirm::not_requested(self.returned_messages.clone()));
+            let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Publish(
+                protocol::basic::Publish {
+                    exchange: message.exchange,
+                    routing_key: message.routing_key,
+                    mandatory: message.options.mandatory,
+                    immediate: message.options.immediate,
+                },
+            ));
+            frames.extend(self.content_frames(method, message.payload, message.properties));
+            confirms.push(confirm);
+        }
+
+        trace!(channel=%self.id, messages=%confirms.len(), "send_frames");
+        let promise = self.frames.push_frames(frames);
+        self.wake();
+        promise.await?;
+        Ok(BatchConfirm::new(confirms))
+    }
+
     #[cfg(test)]
     pub(crate) fn register_queue(
         &self,
@@ -375,10 +752,53 @@ impl Channel {
     async fn send_method_frame_with_body(
         &self,
         method: AMQPClass,
-        payload: Vec<u8>,
+        payload: Bytes,
         properties: BasicProperties,
         publisher_confirms_result: Option<PublisherConfirm>,
     ) -> Result<PublisherConfirm> {
+        let frames = self.content_frames(method, payload, properties);
+        trace!(channel=%self.id, "send_frames");
+        let promise = self.frames.push_frames(frames);
+        self.wake();
+        promise.await?;
+        Ok(publisher_confirms_result
+            .unwrap_or_else(|| PublisherConfirm::not_requested(self.returned_messages.clone())))
+    }
+
+    // Honour connection.blocked and channel.flow before publishing
+    async fn wait_for_publish_permission(&self) -> Result<()> {
+        loop {
+            let blocked = self.connection_status.blocked_reason();
+            let flow = self.status.flow();
+            if blocked.is_none() && flow {
+                return Ok(());
+            }
+            if self.configuration.publish_backpressure() == PublishBackpressure::FailFast {
+                return Err(match blocked {
+                    Some(reason) => Error::ConnectionBlocked(reason),
+                    None => Error::ChannelFlowPaused(self.id),
+                });
+            }
+            if blocked.is_some() {
+                trace!(channel=%self.id, "Waiting for the connection to be unblocked");
+                self.connection_status.until_unblocked().await;
+            } else {
+                trace!(channel=%self.id, "Waiting for the channel flow to resume");
+                self.status.until_flow().await;
+            }
+            if !self.connection_status.connected() || !self.status.connected() {
+                // Let the publish fail with the right error
+                return Ok(());
+            }
+        }
+    }
+
+    fn content_frames(
+        &self,
+        method: AMQPClass,
+        payload: Bytes,
+        properties: BasicProperties,
+    ) -> Vec<AMQPFrame> {
         let class_id = method.get_amqp_class_id();
         let header = AMQPContentHeader {
             class_id,
@@ -392,19 +812,15 @@ impl Channel {
         ];
 
         // a content body frame 8 bytes of overhead
-        frames.extend(
-            payload
-                .as_slice()
-                .chunks(frame_max as usize - 8)
-                .map(|chunk| AMQPFrame::Body(self.id, chunk.into())),
-        );
-
-        trace!(channel=%self.id, "send_frames");
-        let promise = self.frames.push_frames(frames);
-        self.wake();
-        promise.await?;
-        Ok(publisher_confirms_result
-            .unwrap_or_else(|| PublisherConfirm::not_requested(self.returned_messages.clone())))
+        let chunk_size = frame_max as usize - 8;
+        let mut payload = payload;
+        while !payload.is_empty() {
+            let chunk = payload.split_to(std::cmp::min(chunk_size, payload.len()));
+            // Body frames own their content as a Vec: this copies the chunk when it's shared, see
+            // the basic_publish documentation
+            frames.push(AMQPFrame::Body(self.id, chunk.into()));
+        }
+        frames
     }
 
     fn handle_invalid_contents(
@@ -536,10 +952,10 @@ impl Channel {
         &self,
         resolver: PromiseResolver<Connection>,
         connection: Connection,
-        credentials: Credentials,
+        sasl: Box<dyn SaslSession>,
     ) {
         self.connection_status
-            .set_connection_step(ConnectionStep::StartOk(resolver, connection, credentials));
+            .set_connection_step(ConnectionStep::StartOk(resolver, connection, sasl));
     }
 
     fn on_connection_open_sent(&self, resolver: PromiseResolver<Connection>) {
@@ -560,8 +976,9 @@ impl Channel {
             .next_expected_close_ok_reply(self.id, Error::InvalidChannelState(ChannelState::Closed))
     }
 
-    fn before_channel_close(&self) {
+    fn before_channel_close(&self, reply_code: ReplyCode, reply_text: &str) {
         self.set_closing(None);
+        self.status.set_close_reply(reply_code, reply_text.into());
     }
 
     fn on_channel_close_ok_sent(&self, error: Error) {
@@ -570,18 +987,25 @@ impl Channel {
 
     fn on_basic_recover_async_sent(&self) {
         self.consumers.drop_prefetched_messages();
+        self.status.clear_deliveries();
     }
 
     fn on_basic_ack_sent(&self, multiple: bool, delivery_tag: DeliveryTag) {
         if multiple && delivery_tag == 0 {
             self.consumers.drop_prefetched_messages();
         }
+        self.status.complete_delivery(delivery_tag, multiple);
     }
 
     fn on_basic_nack_sent(&self, multiple: bool, delivery_tag: DeliveryTag) {
         if multiple && delivery_tag == 0 {
             self.consumers.drop_prefetched_messages();
         }
+        self.status.complete_delivery(delivery_tag, multiple);
+    }
+
+    fn on_basic_reject_sent(&self, delivery_tag: DeliveryTag) {
+        self.status.complete_delivery(delivery_tag, false);
     }
 
     fn tune_connection_configuration(
@@ -632,30 +1056,38 @@ impl Channel {
                 resolver,
                 connection,
                 credentials,
-                mechanism,
+                mechanisms,
                 mut options,
             )),
         ) = (state.clone(), self.connection_status.connection_step())
         {
-            let mechanism_str = mechanism.to_string();
-            let locale = options.locale.clone();
-
-            if !method
-                .mechanisms
-                .to_string()
-                .split_whitespace()
-                .any(|m| m == mechanism_str)
-            {
-                error!(%mechanism, "unsupported mechanism");
-            }
-            if !method
-                .locales
-                .to_string()
-                .split_whitespace()
-                .any(|l| l == locale)
-            {
-                error!(%locale, "unsupported locale");
-            }
+            self.connection_status
+                .set_server_properties(method.server_properties.into());
+            let server_mechanisms = method.mechanisms.to_string();
+            let server_mechanisms = server_mechanisms.split_whitespace().collect::<Vec<_>>();
+            let server_locales = method.locales.to_string();
+            let server_locales = server_locales.split_whitespace().collect::<Vec<_>>();
+            let locales = options.preferred_locales();
+            let negotiated = negotiate(&mechanisms, &server_mechanisms, |mechanism| {
+                mechanism.name()
+            })
+            .ok_or_else(|| Error::NoSupportedSaslMechanism(to_strings(&server_mechanisms)))
+            .and_then(|mechanism| {
+                let locale = negotiate(&locales, &server_locales, Clone::clone)
+                    .ok_or_else(|| Error::NoSupportedLocale(to_strings(&server_locales)))?;
+                let mut sasl = mechanism.start(&server_mechanisms, &credentials)?;
+                let response = sasl.initial_response()?;
+                Ok((mechanism.name(), locale.clone(), sasl, response))
+            });
+            let (mechanism, locale, sasl, response) = match negotiated {
+                Ok(negotiated) => negotiated,
+                Err(error) => {
+                    error!(%error, "Failed to negotiate the connection");
+                    resolver.swear(Err(error.clone()));
+                    self.internal_rpc.set_connection_error(error.clone());
+                    return Err(error);
+                }
+            };
 
             if !options.client_properties.contains_key("product")
                 || !options.client_properties.contains_key("version")
@@ -694,12 +1126,12 @@ impl Channel {
                 channel
                     .connection_start_ok(
                         options.client_properties,
-                        &mechanism_str,
-                        &credentials.sasl_auth_string(mechanism),
+                        &mechanism,
+                        &response,
                         &locale,
                         resolver,
                         connection,
-                        credentials,
+                        sasl,
                     )
                     .await
             });
@@ -716,14 +1148,26 @@ impl Channel {
         trace!(?method, "Server sent connection::Secure");
 
         let state = self.connection_status.state();
-        if let (ConnectionState::Connecting, Some(ConnectionStep::StartOk(.., credentials))) =
-            (state.clone(), self.connection_status.connection_step())
+        if let (
+            ConnectionState::Connecting,
+            Some(ConnectionStep::StartOk(resolver, connection, mut sasl)),
+        ) = (state.clone(), self.connection_status.connection_step())
         {
+            let response = match sasl.challenge(method.challenge.as_bytes()) {
+                Ok(response) => response,
+                Err(error) => {
+                    error!(%error, "SASL authentication failed");
+                    resolver.swear(Err(error.clone()));
+                    self.internal_rpc.set_connection_error(error.clone());
+                    return Err(error);
+                }
+            };
+            // The server may send more challenges before tuning the connection
+            self.connection_status
+                .set_connection_step(ConnectionStep::StartOk(resolver, connection, sasl));
             let channel = self.clone();
             self.internal_rpc.register_internal_future(async move {
-                channel
-                    .connection_secure_ok(&credentials.rabbit_cr_demo_answer())
-                    .await
+                channel.connection_secure_ok(&response).await
             });
             Ok(())
         } else {
@@ -781,6 +1225,7 @@ impl Channel {
             (state.clone(), self.connection_status.connection_step())
         {
             self.connection_status.set_state(ConnectionState::Connected);
+            self.connection_status.emit(Event::Connected);
             resolver.swear(Ok(connection));
             Ok(())
         } else {
@@ -792,6 +1237,10 @@ impl Channel {
     }
 
     fn on_connection_close_received(&self, method: protocol::connection::Close) -> Result<()> {
+        self.connection_status.emit(Event::ConnectionClosed {
+            reply_code: method.reply_code,
+            reply_text: method.reply_text.to_string(),
+        });
         let error = AMQPError::try_from(method.clone())
             .map(|error| {
                 error!(
@@ -816,8 +1265,10 @@ impl Channel {
         Ok(())
     }
 
-    fn on_connection_blocked_received(&self, _method: protocol::connection::Blocked) -> Result<()> {
-        self.connection_status.block();
+    fn on_connection_blocked_received(&self, method: protocol::connection::Blocked) -> Result<()> {
+        self.connection_status.block(method.reason.as_str());
+        self.connection_status
+            .emit(Event::Blocked(method.reason.to_string()));
         Ok(())
     }
 
@@ -826,6 +1277,7 @@ impl Channel {
         _method: protocol::connection::Unblocked,
     ) -> Result<()> {
         self.connection_status.unblock();
+        self.connection_status.emit(Event::Unblocked);
         self.wake();
         Ok(())
     }
@@ -843,6 +1295,7 @@ impl Channel {
         channel: Channel,
     ) -> Result<()> {
         self.set_state(ChannelState::Connected);
+        self.connection_status.emit(Event::ChannelOpened(self.id));
         resolver.swear(Ok(channel));
         Ok(())
     }
@@ -871,6 +1324,8 @@ impl Channel {
     }
 
     fn on_channel_close_received(&self, method: protocol::channel::Close) -> Result<()> {
+        self.status
+            .set_close_reply(method.reply_code, method.reply_text.to_string());
         let error = AMQPError::try_from(method.clone()).map(|error| {
             error!(
                 channel=%self.id, ?method, ?error,
@@ -1032,6 +1487,9 @@ impl Channel {
         options: BasicGetOptions,
     ) -> Result<()> {
         let class_id = method.get_amqp_class_id();
+        if !options.no_ack {
+            self.status.register_delivery(method.delivery_tag);
+        }
         self.basic_get_delivery.start_new_delivery(
             queue,
             options,
@@ -1073,16 +1531,31 @@ impl Channel {
         options: BasicConsumeOptions,
         arguments: FieldTable,
         original: Option<Consumer>,
+        streaming: bool,
     ) -> Result<()> {
+        if let Some(original) = original.as_ref() {
+            original.resubscribed();
+        }
         let consumer = original.unwrap_or_else(|| {
-            Consumer::new(
+            let consumer = Consumer::new(
                 method.consumer_tag.clone(),
                 self.executor.clone(),
                 channel_closer,
                 queue,
                 options,
                 arguments,
-            )
+            );
+            consumer.set_buffer(ConsumerBuffer::new(
+                self.id,
+                options.no_ack,
+                self.configuration.clone(),
+                self.internal_rpc.clone(),
+                self.waker.clone(),
+            ));
+            if streaming {
+                consumer.set_streaming();
+            }
+            consumer
         });
         let external_consumer = consumer.external(self.id, self.internal_rpc.clone());
         self.consumers.register(method.consumer_tag, consumer);
@@ -1093,6 +1566,9 @@ impl Channel {
     fn on_basic_deliver_received(&self, method: protocol::basic::Deliver) -> Result<()> {
         let class_id = method.get_amqp_class_id();
         let consumer_tag = method.consumer_tag.clone();
+        if self.consumers.no_ack(&consumer_tag) == Some(false) {
+            self.status.register_delivery(method.delivery_tag);
+        }
         self.consumers.start_delivery(&consumer_tag, |error| {
             Delivery::new(
                 self.id,
@@ -1110,7 +1586,14 @@ impl Channel {
     }
 
     fn on_basic_cancel_received(&self, method: protocol::basic::Cancel) -> Result<()> {
-        self.consumers.deregister(method.consumer_tag.as_str());
+        if let Some((consumer, policy)) = self.consumers.server_cancel(method.consumer_tag.as_str())
+        {
+            self.resubscribe(consumer, policy);
+        }
+        self.connection_status.emit(Event::ConsumerCancelled {
+            channel_id: self.id,
+            consumer_tag: method.consumer_tag.to_string(),
+        });
         if !method.nowait {
             let channel = self.clone();
             self.internal_rpc.register_internal_future(async move {
@@ -1203,6 +1686,7 @@ impl Channel {
 
     fn on_basic_recover_ok_received(&self) -> Result<()> {
         self.consumers.drop_prefetched_messages();
+        self.status.clear_deliveries();
         Ok(())
     }
 
@@ -1211,11 +1695,56 @@ impl Channel {
         Ok(())
     }
 
+    fn on_tx_select_ok_received(&self) -> Result<()> {
+        self.status.set_transactional();
+        Ok(())
+    }
+
     fn on_access_request_ok_received(&self, _: protocol::access::RequestOk) -> Result<()> {
         Ok(())
     }
 }
 
+// Resolves once all the deliveries got acked, nacked or rejected and all the publishes got
+// confirmed, or once the channel isn't connected anymore
+struct Drained {
+    status: ChannelStatus,
+    acknowledgements: Acknowledgements,
+}
+
+impl Future for Drained {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Register first so that we don't miss a change happening while we check
+        self.status.register_drain_waker(cx.waker());
+        self.acknowledgements.register_drain_waker(cx.waker());
+        if !self.status.connected()
+            || (self.status.unacked_deliveries().is_empty()
+                && self.acknowledgements.pending().is_empty())
+        {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Pick the first of our preferences which is offered by the server
+fn negotiate<'a, T>(
+    preferences: &'a [T],
+    offered: &[&str],
+    name: impl Fn(&T) -> String,
+) -> Option<&'a T> {
+    preferences
+        .iter()
+        .find(|preference| offered.contains(&name(preference).as_str()))
+}
+
+fn to_strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(ToString::to_string).collect()
+}
+
 #[cfg(feature = "codegen")]
 include!(concat!(env!("OUT_DIR"), "/channel.rs"));
 #[cfg(not(feature = "codegen"))]
@@ -1,10 +1,18 @@
 use crate::{
     channel_receiver_state::{ChannelReceiverStates, DeliveryCause},
-    types::{ChannelId, Identifier, PayloadSize},
+    types::{ChannelId, DeliveryTag, Identifier, PayloadSize, ReplyCode},
+    wakers::Wakers,
     Result,
 };
 use parking_lot::Mutex;
-use std::{fmt, sync::Arc};
+use std::{
+    collections::BTreeSet,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
 use tracing::trace;
 
 #[derive(Clone, Default)]
@@ -36,12 +44,28 @@ impl ChannelStatus {
         trace!("Publisher confirms activated");
     }
 
+    /// Whether the channel is in transactional mode, see [`Channel::transaction`]
+    ///
+    /// [`Channel::transaction`]: ./struct.Channel.html#method.transaction
+    pub fn transactional(&self) -> bool {
+        self.0.lock().transactional
+    }
+
+    pub(crate) fn set_transactional(&self) {
+        self.0.lock().transactional = true;
+        trace!("Transactional mode activated");
+    }
+
     pub fn state(&self) -> ChannelState {
         self.0.lock().state.clone()
     }
 
     pub(crate) fn set_state(&self, state: ChannelState) {
-        self.0.lock().state = state;
+        let mut inner = self.0.lock();
+        inner.state = state;
+        // Don't keep publishers waiting for a flow which will never resume
+        inner.flow_wakers.wake();
+        inner.drain_wakers.wake();
     }
 
     pub(crate) fn auto_close(&self, id: ChannelId) -> bool {
@@ -103,8 +127,85 @@ impl ChannelStatus {
             .receive(channel_id, length, handler, error_handler, confirm_mode)
     }
 
+    pub(crate) fn reset(&self) {
+        let mut inner = self.0.lock();
+        inner.receiver_state = ChannelReceiverStates::default();
+        inner.send_flow = true;
+        inner.flow_wakers.wake();
+        inner.unacked.clear();
+        inner.drain_wakers.wake();
+    }
+
+    /// The deliveries we received and didn't ack, nack or reject yet
+    pub(crate) fn unacked_deliveries(&self) -> Vec<DeliveryTag> {
+        self.0.lock().unacked.iter().copied().collect()
+    }
+
+    /// The last of the first unacked deliveries matching `predicate`
+    pub(crate) fn last_contiguous_delivery<F: Fn(DeliveryTag) -> bool>(
+        &self,
+        predicate: F,
+    ) -> Option<DeliveryTag> {
+        self.0
+            .lock()
+            .unacked
+            .iter()
+            .copied()
+            .take_while(|tag| predicate(*tag))
+            .last()
+    }
+
+    pub(crate) fn register_delivery(&self, delivery_tag: DeliveryTag) {
+        self.0.lock().unacked.insert(delivery_tag);
+    }
+
+    pub(crate) fn complete_delivery(&self, delivery_tag: DeliveryTag, multiple: bool) {
+        let mut inner = self.0.lock();
+        if multiple {
+            if delivery_tag == 0 {
+                inner.unacked.clear();
+            } else {
+                inner.unacked = inner.unacked.split_off(&(delivery_tag + 1));
+            }
+        } else {
+            inner.unacked.remove(&delivery_tag);
+        }
+        if inner.unacked.is_empty() {
+            inner.drain_wakers.wake();
+        }
+    }
+
+    pub(crate) fn clear_deliveries(&self) {
+        let mut inner = self.0.lock();
+        inner.unacked.clear();
+        inner.drain_wakers.wake();
+    }
+
+    /// Wake `waker` up once all the deliveries got acked, nacked or rejected, or once the state of
+    /// the channel changes
+    pub(crate) fn register_drain_waker(&self, waker: &Waker) {
+        self.0.lock().drain_wakers.register(waker);
+    }
+
+    /// Remember why the channel is getting closed, until the close completes
+    pub(crate) fn set_close_reply(&self, reply_code: ReplyCode, reply_text: String) {
+        self.0.lock().close_reply = Some((reply_code, reply_text));
+    }
+
+    pub(crate) fn take_close_reply(&self) -> Option<(ReplyCode, String)> {
+        self.0.lock().close_reply.take()
+    }
+
     pub(crate) fn set_send_flow(&self, flow: bool) {
-        self.0.lock().send_flow = flow;
+        let mut inner = self.0.lock();
+        inner.send_flow = flow;
+        if flow {
+            inner.flow_wakers.wake();
+        }
+    }
+
+    pub(crate) fn until_flow(&self) -> FlowResumed {
+        FlowResumed(self.clone())
     }
 
     pub(crate) fn flow(&self) -> bool {
@@ -135,7 +236,9 @@ impl fmt::Debug for ChannelStatus {
                 .field("state", &inner.state)
                 .field("receiver_state", &inner.receiver_state)
                 .field("confirm", &inner.confirm)
-                .field("send_flow", &inner.send_flow);
+                .field("transactional", &inner.transactional)
+                .field("send_flow", &inner.send_flow)
+                .field("unacked", &inner.unacked);
         }
         debug.finish()
     }
@@ -143,18 +246,45 @@ impl fmt::Debug for ChannelStatus {
 
 struct Inner {
     confirm: bool,
+    transactional: bool,
     send_flow: bool,
     state: ChannelState,
     receiver_state: ChannelReceiverStates,
+    unacked: BTreeSet<DeliveryTag>,
+    flow_wakers: Wakers,
+    drain_wakers: Wakers,
+    close_reply: Option<(ReplyCode, String)>,
 }
 
 impl Default for Inner {
     fn default() -> Self {
         Self {
             confirm: false,
+            transactional: false,
             send_flow: true,
             state: ChannelState::default(),
             receiver_state: ChannelReceiverStates::default(),
+            unacked: BTreeSet::default(),
+            flow_wakers: Wakers::default(),
+            drain_wakers: Wakers::default(),
+            close_reply: None,
+        }
+    }
+}
+
+// Resolves once the server resumed the flow of the channel, or once the channel got closed
+pub(crate) struct FlowResumed(ChannelStatus);
+
+impl Future for FlowResumed {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = (self.0).0.lock();
+        if inner.send_flow || inner.state != ChannelState::Connected {
+            Poll::Ready(())
+        } else {
+            inner.flow_wakers.register(cx.waker());
+            Poll::Pending
         }
     }
 }
@@ -1,6 +1,7 @@
 use crate::{
     connection_closer::ConnectionCloser,
     error_handler::ErrorHandler,
+    events::Event,
     frames::Frames,
     id_sequence::IdSequence,
     internal_rpc::InternalRPCHandle,
@@ -15,8 +16,9 @@ use crate::{
 use amq_protocol::frame::{AMQPFrame, ProtocolVersion};
 use executor_trait::FullExecutor;
 use parking_lot::Mutex;
+use reactor_trait::Reactor;
 use std::{collections::HashMap, fmt, sync::Arc};
-use tracing::{debug, error, level_enabled, trace, Level};
+use tracing::{debug, error, level_enabled, trace, warn, Level};
 
 #[derive(Clone)]
 pub(crate) struct Channels {
@@ -25,6 +27,7 @@ pub(crate) struct Channels {
     global_registry: Registry,
     internal_rpc: InternalRPCHandle,
     executor: Arc<dyn FullExecutor + Send + Sync>,
+    reactor: Arc<dyn Reactor + Send + Sync>,
     frames: Frames,
     error_handler: ErrorHandler,
 }
@@ -38,6 +41,7 @@ impl Channels {
         internal_rpc: InternalRPCHandle,
         frames: Frames,
         executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn Reactor + Send + Sync>,
     ) -> Self {
         Self {
             inner: Arc::new(Mutex::new(Inner::new(configuration, waker))),
@@ -45,6 +49,7 @@ impl Channels {
             global_registry,
             internal_rpc,
             executor,
+            reactor,
             frames,
             error_handler: ErrorHandler::default(),
         }
@@ -57,6 +62,7 @@ impl Channels {
             self.internal_rpc.clone(),
             self.frames.clone(),
             self.executor.clone(),
+            self.reactor.clone(),
             connection_closer,
         )
     }
@@ -71,6 +77,7 @@ impl Channels {
                 self.internal_rpc.clone(),
                 self.frames.clone(),
                 self.executor.clone(),
+                self.reactor.clone(),
                 None,
             )
             .set_state(ChannelState::Connected);
@@ -80,6 +87,17 @@ impl Channels {
         self.inner.lock().channels.get(&id).cloned()
     }
 
+    /// All the channels but channel 0
+    pub(crate) fn list(&self) -> Vec<Channel> {
+        self.inner
+            .lock()
+            .channels
+            .values()
+            .filter(|c| c.id() != 0)
+            .cloned()
+            .collect()
+    }
+
     pub(crate) fn remove(&self, id: ChannelId, error: Error) -> Result<()> {
         self.frames.clear_expected_replies(id, error);
         if self.inner.lock().channels.remove(&id).is_some() {
@@ -122,6 +140,7 @@ impl Channels {
 
     pub(crate) fn set_connection_closed(&self, error: Error) {
         self.connection_status.set_state(ConnectionState::Closed);
+        self.connection_status.unblock();
         for (id, channel) in self.inner.lock().channels.drain() {
             self.frames.clear_expected_replies(id, error.clone());
             channel.set_state(ChannelState::Closed);
@@ -137,6 +156,9 @@ impl Channels {
 
         error!(%error, "Connection error");
         self.connection_status.set_state(ConnectionState::Error);
+        self.connection_status.unblock();
+        self.connection_status
+            .emit(Event::ConnectionError(error.clone()));
         self.frames.drop_pending(error.clone());
         self.error_handler.on_error(error.clone());
         for (id, channel) in self.inner.lock().channels.drain() {
@@ -147,6 +169,23 @@ impl Channels {
         }
     }
 
+    pub(crate) fn set_connection_recovering(&self, error: Error) {
+        warn!(%error, "Connection lost, trying to recover");
+        self.connection_status.set_state(ConnectionState::Reconnecting);
+        // The new connection won't be blocked
+        self.connection_status.unblock();
+        self.connection_status
+            .emit(Event::Reconnecting(error.clone()));
+        self.frames.drop_pending(error.clone());
+        for channel in self.inner.lock().channels.values() {
+            channel.set_recovering(error.clone());
+        }
+    }
+
+    pub(crate) fn report_error(&self, error: Error) {
+        self.error_handler.on_error(error);
+    }
+
     pub(crate) fn flow(&self) -> bool {
         self.inner
             .lock()
@@ -316,6 +355,7 @@ impl Inner {
         internal_rpc: InternalRPCHandle,
         frames: Frames,
         executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn Reactor + Send + Sync>,
         connection_closer: Option<Arc<ConnectionCloser>>,
     ) -> Channel {
         debug!(%id, "create channel");
@@ -328,6 +368,7 @@ impl Inner {
             internal_rpc,
             frames,
             executor,
+            reactor,
             connection_closer,
         );
         self.channels.insert(id, channel.clone_internal());
@@ -341,6 +382,7 @@ impl Inner {
         internal_rpc: InternalRPCHandle,
         frames: Frames,
         executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn Reactor + Send + Sync>,
         connection_closer: Arc<ConnectionCloser>,
     ) -> Result<Channel> {
         debug!("create channel");
@@ -363,6 +405,7 @@ impl Inner {
                     internal_rpc,
                     frames,
                     executor,
+                    reactor,
                     Some(connection_closer),
                 ));
             }
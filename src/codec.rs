@@ -0,0 +1,342 @@
+use crate::{
+    acker::Acker, message::Delivery, options::BasicPublishOptions,
+    publisher_confirm::PublisherConfirm, BasicProperties, Channel, Consumer, Error, Result,
+};
+use futures_lite::Stream;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fmt,
+    marker::PhantomData,
+    ops::Deref,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A serialization format for message payloads, each one being enabled by its cargo feature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Codec {
+    /// JSON, using `serde_json`
+    #[cfg(feature = "json")]
+    Json,
+    /// CBOR, using `ciborium`
+    #[cfg(feature = "cbor")]
+    Cbor,
+    /// MessagePack, using `rmp-serde`
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+impl Codec {
+    /// The content type set on the messages encoded with this codec
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "json")]
+            Codec::Json => "application/json",
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => "application/cbor",
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => "application/msgpack",
+        }
+    }
+
+    /// Find the codec matching the content type of a message
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        // Ignore parameters such as "; charset=utf-8"
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        match mime {
+            #[cfg(feature = "json")]
+            "application/json" => Some(Codec::Json),
+            #[cfg(feature = "cbor")]
+            "application/cbor" => Some(Codec::Cbor),
+            #[cfg(feature = "msgpack")]
+            "application/msgpack" | "application/x-msgpack" => Some(Codec::MessagePack),
+            _ => None,
+        }
+    }
+
+    /// Serialize `value`
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "json")]
+            Codec::Json => serde_json::to_vec(value).map_err(codec_error),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => {
+                let mut data = Vec::new();
+                ciborium::ser::into_writer(value, &mut data).map_err(codec_error)?;
+                Ok(data)
+            }
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => rmp_serde::to_vec_named(value).map_err(codec_error),
+        }
+    }
+
+    /// Deserialize a value from `data`
+    pub fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        match self {
+            #[cfg(feature = "json")]
+            Codec::Json => serde_json::from_slice(data).map_err(codec_error),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => ciborium::de::from_reader(data).map_err(codec_error),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => rmp_serde::from_slice(data).map_err(codec_error),
+        }
+    }
+}
+
+fn codec_error<E: fmt::Display>(error: E) -> Error {
+    Error::CodecError(error.to_string())
+}
+
+impl Channel {
+    /// Serialize `value` using `codec` and publish it, setting the content type accordingly.
+    ///
+    /// The content encoding is left untouched: it describes a transformation applied on top of
+    /// the content type, such as compression, and the codecs produce the plain serialized value.
+    /// [`Consumer::typed`] refuses payloads with an encoding other than `identity`.
+    ///
+    /// [`Consumer::typed`]: ./struct.Consumer.html#method.typed
+    pub async fn publish_typed<T: Serialize>(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        value: &T,
+        codec: Codec,
+        properties: BasicProperties,
+    ) -> Result<PublisherConfirm> {
+        let payload = codec.encode(value)?;
+        let properties = properties.with_content_type(codec.content_type().into());
+        self.basic_publish(exchange, routing_key, options, payload, properties)
+            .await
+    }
+}
+
+impl Consumer {
+    /// Decode the payload of each delivery, picking the [`Codec`] from its content type.
+    ///
+    /// A message which can't be decoded doesn't end the stream: its [`TypedDelivery`] carries
+    /// the error instead, along with the raw delivery so that it can be rejected.
+    ///
+    /// [`Codec`]: ./enum.Codec.html
+    /// [`TypedDelivery`]: ./struct.TypedDelivery.html
+    pub fn typed<T: DeserializeOwned>(self) -> TypedConsumer<T> {
+        TypedConsumer {
+            consumer: self,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// A [`Consumer`] decoding the payload of its deliveries, see [`Consumer::typed`].
+///
+/// [`Consumer`]: ./struct.Consumer.html
+/// [`Consumer::typed`]: ./struct.Consumer.html#method.typed
+pub struct TypedConsumer<T> {
+    consumer: Consumer,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> TypedConsumer<T> {
+    /// The underlying consumer
+    pub fn consumer(&self) -> &Consumer {
+        &self.consumer
+    }
+}
+
+impl<T> fmt::Debug for TypedConsumer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TypedConsumer")
+            .field(&self.consumer)
+            .finish()
+    }
+}
+
+impl<T: DeserializeOwned> Stream for TypedConsumer<T> {
+    type Item = Result<TypedDelivery<T>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.consumer).poll_next(cx).map(|delivery| {
+            delivery.map(|delivery| {
+                delivery.map(|delivery| TypedDelivery {
+                    value: decode(&delivery),
+                    delivery,
+                })
+            })
+        })
+    }
+}
+
+fn decode<T: DeserializeOwned>(delivery: &Delivery) -> Result<T> {
+    if let Some(content_encoding) = delivery
+        .properties
+        .content_encoding()
+        .as_ref()
+        .filter(|content_encoding| content_encoding.as_str() != "identity")
+    {
+        return Err(Error::CodecError(format!(
+            "unsupported content encoding: {}",
+            content_encoding
+        )));
+    }
+    let content_type = delivery
+        .properties
+        .content_type()
+        .as_ref()
+        .map(|content_type| content_type.as_str())
+        .ok_or_else(|| Error::CodecError("missing content type".into()))?;
+    Codec::from_content_type(content_type)
+        .ok_or_else(|| Error::CodecError(format!("unsupported content type: {}", content_type)))?
+        .decode(&delivery.data)
+}
+
+/// A delivery decoded by a [`TypedConsumer`]
+///
+/// [`TypedConsumer`]: ./struct.TypedConsumer.html
+#[derive(Debug)]
+pub struct TypedDelivery<T> {
+    /// The decoded payload, or why it couldn't be decoded
+    pub value: Result<T>,
+    /// The raw delivery, carrying the properties and the acker of the message
+    pub delivery: Delivery,
+}
+
+impl<T> Deref for TypedDelivery<T> {
+    type Target = Acker;
+
+    fn deref(&self) -> &Self::Target {
+        &self.delivery.acker
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        options::BasicConsumeOptions,
+        types::{FieldTable, ShortString},
+    };
+    use futures_lite::{future, StreamExt};
+    use serde::Deserialize;
+    use std::sync::Arc;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        id: u64,
+        items: Vec<String>,
+    }
+
+    fn order() -> Order {
+        Order {
+            id: 42,
+            items: vec!["book".into(), "pen".into()],
+        }
+    }
+
+    fn round_trip(codec: Codec) {
+        let data = codec.encode(&order()).unwrap();
+        assert_eq!(codec.decode::<Order>(&data), Ok(order()));
+        assert_eq!(Codec::from_content_type(codec.content_type()), Some(codec));
+        assert!(matches!(
+            codec.decode::<Order>(b"\xff\x00garbage"),
+            Err(Error::CodecError(_))
+        ));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json() {
+        round_trip(Codec::Json);
+        assert_eq!(
+            Codec::from_content_type("application/json; charset=utf-8"),
+            Some(Codec::Json)
+        );
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor() {
+        round_trip(Codec::Cbor);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack() {
+        round_trip(Codec::MessagePack);
+        assert_eq!(
+            Codec::from_content_type("application/x-msgpack"),
+            Some(Codec::MessagePack)
+        );
+    }
+
+    #[test]
+    fn typed_consumer_keeps_going_after_bad_payload() {
+        let consumer = Consumer::new(
+            ShortString::from("test-consumer"),
+            Arc::new(async_global_executor_trait::AsyncGlobalExecutor),
+            None,
+            "test".into(),
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        );
+        let codec = [
+            #[cfg(feature = "json")]
+            Codec::Json,
+            #[cfg(feature = "cbor")]
+            Codec::Cbor,
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack,
+        ][0];
+        let deliver = |delivery_tag, properties: BasicProperties, payload: Vec<u8>| {
+            consumer.start_new_delivery(Delivery::new(
+                1,
+                delivery_tag,
+                "".into(),
+                "test".into(),
+                false,
+                None,
+                None,
+            ));
+            consumer.handle_content_header_frame(payload.len() as _, properties);
+            consumer.handle_body_frame(0, payload);
+        };
+        let content_type =
+            BasicProperties::default().with_content_type(codec.content_type().into());
+        deliver(1, content_type.clone(), b"\xff\x00garbage".to_vec());
+        deliver(
+            2,
+            BasicProperties::default(),
+            codec.encode(&order()).unwrap(),
+        );
+        deliver(
+            3,
+            content_type.clone().with_content_encoding("gzip".into()),
+            codec.encode(&order()).unwrap(),
+        );
+        deliver(4, content_type, codec.encode(&order()).unwrap());
+
+        let mut typed = consumer.clone().typed::<Order>();
+        let mut next = || {
+            let delivery = future::block_on(typed.next())
+                .expect("the stream ended")
+                .expect("the consumer failed");
+            (delivery.delivery.delivery_tag, delivery.value)
+        };
+        assert!(matches!(next(), (1, Err(Error::CodecError(_)))));
+        assert_eq!(
+            next(),
+            (2, Err(Error::CodecError("missing content type".into())))
+        );
+        assert_eq!(
+            next(),
+            (
+                3,
+                Err(Error::CodecError(
+                    "unsupported content encoding: gzip".into()
+                ))
+            )
+        );
+        assert_eq!(next(), (4, Ok(order())));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{
+    consumer_buffer::{BufferLimits, BufferOverflowPolicy, BufferUsage},
     protocol,
     types::{ChannelId, FrameSize, Heartbeat},
 };
@@ -8,6 +9,7 @@ use std::{fmt, sync::Arc};
 #[derive(Clone, Default)]
 pub struct Configuration {
     inner: Arc<RwLock<Inner>>,
+    buffer_usage: BufferUsage,
 }
 
 impl Configuration {
@@ -35,6 +37,60 @@ impl Configuration {
     pub(crate) fn set_heartbeat(&self, heartbeat: Heartbeat) {
         self.inner.write().heartbeat = heartbeat;
     }
+
+    pub fn publish_backpressure(&self) -> PublishBackpressure {
+        self.inner.read().publish_backpressure
+    }
+
+    pub(crate) fn set_publish_backpressure(&self, publish_backpressure: PublishBackpressure) {
+        self.inner.write().publish_backpressure = publish_backpressure;
+    }
+
+    pub fn consumer_buffer_limits(&self) -> BufferLimits {
+        self.inner.read().consumer_buffer_limits
+    }
+
+    pub(crate) fn set_consumer_buffer_limits(&self, limits: BufferLimits) {
+        self.inner.write().consumer_buffer_limits = limits;
+    }
+
+    pub fn connection_buffer_limits(&self) -> BufferLimits {
+        self.inner.read().connection_buffer_limits
+    }
+
+    pub(crate) fn set_connection_buffer_limits(&self, limits: BufferLimits) {
+        self.inner.write().connection_buffer_limits = limits;
+    }
+
+    pub fn buffer_overflow_policy(&self) -> BufferOverflowPolicy {
+        self.inner.read().buffer_overflow_policy
+    }
+
+    pub(crate) fn set_buffer_overflow_policy(&self, policy: BufferOverflowPolicy) {
+        self.inner.write().buffer_overflow_policy = policy;
+    }
+
+    pub(crate) fn buffer_usage(&self) -> &BufferUsage {
+        &self.buffer_usage
+    }
+}
+
+/// What publishing does while the server blocked the connection or paused the channel flow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PublishBackpressure {
+    /// Wait until the server lets us publish again
+    Wait,
+    /// Fail right away with [`Error::ConnectionBlocked`] or [`Error::ChannelFlowPaused`]
+    ///
+    /// [`Error::ConnectionBlocked`]: ./enum.Error.html#variant.ConnectionBlocked
+    /// [`Error::ChannelFlowPaused`]: ./enum.Error.html#variant.ChannelFlowPaused
+    FailFast,
+}
+
+impl Default for PublishBackpressure {
+    fn default() -> Self {
+        Self::Wait
+    }
 }
 
 #[derive(Default)]
@@ -42,6 +98,10 @@ struct Inner {
     channel_max: ChannelId,
     frame_max: FrameSize,
     heartbeat: Heartbeat,
+    publish_backpressure: PublishBackpressure,
+    consumer_buffer_limits: BufferLimits,
+    connection_buffer_limits: BufferLimits,
+    buffer_overflow_policy: BufferOverflowPolicy,
 }
 
 impl fmt::Debug for Configuration {
@@ -51,6 +111,11 @@ impl fmt::Debug for Configuration {
             .field("channel_max", &inner.channel_max)
             .field("frame_max", &inner.frame_max)
             .field("heartbeat", &inner.heartbeat)
+            .field("publish_backpressure", &inner.publish_backpressure)
+            .field("consumer_buffer_limits", &inner.consumer_buffer_limits)
+            .field("connection_buffer_limits", &inner.connection_buffer_limits)
+            .field("buffer_overflow_policy", &inner.buffer_overflow_policy)
+            .field("buffer_usage", &self.buffer_usage)
             .finish()
     }
 }
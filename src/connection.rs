@@ -1,31 +1,44 @@
 use crate::{
+    auth::Credentials,
     channel::Channel,
     channels::Channels,
     configuration::Configuration,
     connection_closer::ConnectionCloser,
     connection_properties::ConnectionProperties,
-    connection_status::{ConnectionState, ConnectionStatus, ConnectionStep},
+    connection_status::{ConnectionState, ConnectionStatus, ConnectionStep, Unblocked},
+    credentials_provider::{fetch_credentials, SecretRefresh},
+    endpoints::Endpoints,
+    events::{Event, EventStream},
     frames::Frames,
     heartbeat::Heartbeat,
     internal_rpc::{InternalRPC, InternalRPCHandle},
     io_loop::IoLoop,
-    options::{ExchangeBindOptions, QueueBindOptions},
+    options::{ConfirmSelectOptions, ExchangeBindOptions, QueueBindOptions},
+    protocol,
+    recovery::Recovery,
     registry::Registry,
     socket_state::{SocketState, SocketStateHandle},
-    tcp::{AMQPUriTcpExt, HandshakeResult, OwnedTLSConfig},
+    tcp::{AMQPUriTcpExt, HandshakeError, HandshakeResult, OwnedTLSConfig, TLSConfig},
     thread::ThreadHandle,
     topology::{RestoredChannel, RestoredTopology, TopologyDefinition},
     topology_internal::TopologyInternal,
     types::ReplyCode,
-    uri::AMQPUri,
-    Error, Promise, Result, TcpStream,
+    uri::{AMQPScheme, AMQPUri},
+    ConnectionPhase, Error, Promise, Result, ServerProperties, ShutdownReport, TcpStream,
 };
 use amq_protocol::frame::{AMQPFrame, ProtocolVersion};
 use async_trait::async_trait;
 use executor_trait::FullExecutor;
-use reactor_trait::IOHandle;
-use std::{fmt, io, sync::Arc};
-use tracing::{level_enabled, Level};
+use futures_lite::future;
+use reactor_trait::{IOHandle, Reactor};
+use std::{
+    fmt, io,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+use tracing::{error, info, level_enabled, warn, Level};
+
+pub(crate) type Connector = Arc<dyn Fn(&AMQPUri) -> HandshakeResult + Send + Sync>;
 
 /// A TCP connection to the AMQP server.
 ///
@@ -53,6 +66,7 @@ impl Connection {
         internal_rpc: InternalRPCHandle,
         frames: Frames,
         executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn Reactor + Send + Sync>,
     ) -> Self {
         let configuration = Configuration::default();
         let status = ConnectionStatus::default();
@@ -65,6 +79,7 @@ impl Connection {
             internal_rpc.clone(),
             frames,
             executor,
+            reactor,
         );
         let closer = Arc::new(ConnectionCloser::new(status.clone(), internal_rpc));
         let connection = Self {
@@ -117,6 +132,46 @@ impl Connection {
         Connect::connect(uri, options, config).await
     }
 
+    /// Connect to the first available AMQP Server amongst `uris`.
+    ///
+    /// The endpoints are tried in an order depending on the [`EndpointSelection`] set in the
+    /// [`ConnectionProperties`].
+    ///
+    /// [`EndpointSelection`]: ./enum.EndpointSelection.html
+    /// [`ConnectionProperties`]: ./struct.ConnectionProperties.html
+    pub async fn connect_endpoints(
+        uris: &[&str],
+        options: ConnectionProperties,
+    ) -> Result<Connection> {
+        Connect::connect(uris, options, OwnedTLSConfig::default()).await
+    }
+
+    /// Connect to the first available AMQP Server amongst `uris`.
+    pub async fn connect_endpoints_with_config(
+        uris: &[&str],
+        options: ConnectionProperties,
+        config: OwnedTLSConfig,
+    ) -> Result<Connection> {
+        Connect::connect(uris, options, config).await
+    }
+
+    /// Connect to the first available AMQP Server amongst `uris`.
+    pub async fn connect_uris(
+        uris: Vec<AMQPUri>,
+        options: ConnectionProperties,
+    ) -> Result<Connection> {
+        Connect::connect(uris, options, OwnedTLSConfig::default()).await
+    }
+
+    /// Connect to the first available AMQP Server amongst `uris`.
+    pub async fn connect_uris_with_config(
+        uris: Vec<AMQPUri>,
+        options: ConnectionProperties,
+        config: OwnedTLSConfig,
+    ) -> Result<Connection> {
+        Connect::connect(uris, options, config).await
+    }
+
     /// Creates a new [`Channel`] on this connection.
     ///
     /// This method is only successful if the client is connected.
@@ -150,7 +205,16 @@ impl Connection {
                 .push(RestoredChannel::new(if let Some(c) = c.channel.clone() {
                     let channel = c.clone();
                     c.reset();
-                    c.channel_open(channel).await?
+                    let channel = c.channel_open(channel).await?;
+                    if channel.status().confirm() {
+                        channel
+                            .confirm_select(ConfirmSelectOptions::default())
+                            .await?;
+                    }
+                    if channel.status().transactional() {
+                        channel.tx_select().await?;
+                    }
+                    channel
                 } else {
                     self.create_channel().await?
                 }));
@@ -228,6 +292,16 @@ impl Connection {
         Ok(restored)
     }
 
+    pub(crate) async fn restore_after_recovery(&self) {
+        match self.restore_internal(self.topology_internal()).await {
+            Ok(_) => info!("Connection recovered"),
+            Err(error) => {
+                error!(%error, "Failed to restore topology after recovery");
+                self.channels.report_error(error);
+            }
+        }
+    }
+
     /// Block current thread while the connection is still active.
     /// This is useful when you only have a consumer and nothing else keeping your application
     /// "alive".
@@ -241,6 +315,10 @@ impl Connection {
         self.channels.set_error_handler(handler);
     }
 
+    pub(crate) fn report_error(&self, error: Error) {
+        self.channels.report_error(error);
+    }
+
     pub fn configuration(&self) -> &Configuration {
         &self.configuration
     }
@@ -249,15 +327,112 @@ impl Connection {
         &self.status
     }
 
+    /// Subscribe to the lifecycle events of this connection and its channels.
+    ///
+    /// Only the events occurring after the subscription are received.
+    pub fn events(&self) -> EventStream {
+        self.status.subscribe()
+    }
+
+    /// The properties and capabilities advertised by the server during the handshake
+    pub fn server_properties(&self) -> ServerProperties {
+        self.status.server_properties()
+    }
+
     pub async fn close(&self, reply_code: ReplyCode, reply_text: &str) -> Result<()> {
+        let closed = || {
+            self.status.emit(Event::ConnectionClosed {
+                reply_code,
+                reply_text: reply_text.into(),
+            })
+        };
+        if self.status.reconnecting() {
+            // We're not connected to anything yet, nothing to tell the server
+            self.channels
+                .set_connection_closed(Error::InvalidConnectionState(ConnectionState::Closed));
+            closed();
+            return Ok(());
+        }
+
         self.channels.set_connection_closing();
         if let Some(channel0) = self.channels.get(0) {
             channel0
                 .connection_close(reply_code, reply_text, 0, 0)
-                .await
-        } else {
-            Ok(())
+                .await?;
+            // The server acknowledged the close
+            closed();
+        }
+        Ok(())
+    }
+
+    /// Gracefully shut down the connection.
+    ///
+    /// All consumers get cancelled first. Then we wait for the deliveries already received to be
+    /// acked, nacked or rejected and for the pending publisher confirms on every channel, before
+    /// closing the channels and the connection. Whatever didn't complete before `timeout` is
+    /// listed in the returned [`ShutdownReport`].
+    ///
+    /// [`ShutdownReport`]: ./struct.ShutdownReport.html
+    pub async fn shutdown(&self, timeout: Duration) -> Result<ShutdownReport> {
+        let deadline = Instant::now() + timeout;
+        let channels = self.channels.list();
+        let mut report = ShutdownReport::default();
+
+        // First stop receiving new deliveries
+        for channel in channels.iter().filter(|c| c.status().connected()) {
+            let id = channel.id();
+            report.uncancelled_consumers.extend(
+                channel
+                    .cancel_all_consumers(deadline)
+                    .await
+                    .into_iter()
+                    .map(|tag| (id, tag)),
+            );
+        }
+
+        // Then let the in-flight deliveries and publishes complete
+        for channel in &channels {
+            channel.wait_drained(deadline).await;
+        }
+
+        for channel in &channels {
+            let id = channel.id();
+            report.unacked_deliveries.extend(
+                channel
+                    .unacked_deliveries()
+                    .into_iter()
+                    .map(|tag| (id, tag)),
+            );
+            report.unconfirmed_publishes.extend(
+                channel
+                    .unconfirmed_publishes()
+                    .into_iter()
+                    .map(|tag| (id, tag)),
+            );
+            if channel.status().connected() {
+                if let Err(error) = channel
+                    .close(protocol::constants::REPLY_SUCCESS, "OK")
+                    .await
+                {
+                    warn!(channel=%id, %error, "Failed to close channel during shutdown");
+                }
+            }
         }
+
+        self.close(protocol::constants::REPLY_SUCCESS, "OK").await?;
+        Ok(report)
+    }
+
+    /// Why the server blocked this connection, if it did
+    pub fn blocked_reason(&self) -> Option<String> {
+        self.status.blocked_reason()
+    }
+
+    /// Wait until the server unblocks this connection.
+    ///
+    /// This resolves right away if the connection isn't blocked, or once it gets closed.
+    pub fn until_unblocked(&self) -> Unblocked {
+        self.status.until_unblocked()
     }
 
     /// Block all consumers and publishers on this connection
@@ -289,7 +464,23 @@ impl Connection {
 
     pub async fn connector(
         uri: AMQPUri,
-        connect: Box<dyn FnOnce(&AMQPUri) -> HandshakeResult + Send + Sync>,
+        connect: Box<dyn Fn(&AMQPUri) -> HandshakeResult + Send + Sync>,
+        options: ConnectionProperties,
+    ) -> Result<Connection> {
+        Self::connector_with_endpoints(vec![uri], connect, options).await
+    }
+
+    /// Connect to the first available endpoint amongst `uris`.
+    ///
+    /// The endpoints are tried in turn, in an order depending on the [`EndpointSelection`]
+    /// set in the [`ConnectionProperties`]. The same strategy is used when the connection
+    /// gets automatically recovered.
+    ///
+    /// [`EndpointSelection`]: ./enum.EndpointSelection.html
+    /// [`ConnectionProperties`]: ./struct.ConnectionProperties.html
+    pub async fn connector_with_endpoints(
+        uris: Vec<AMQPUri>,
+        connect: Box<dyn Fn(&AMQPUri) -> HandshakeResult + Send + Sync>,
         mut options: ConnectionProperties,
     ) -> Result<Connection> {
         let executor = options
@@ -297,31 +488,19 @@ impl Connection {
             .take()
             .unwrap_or_else(|| Arc::new(async_global_executor_trait::AsyncGlobalExecutor));
 
-        let (connect_promise, resolver) = pinky_swear::PinkySwear::<Result<TcpStream>>::new();
-        let connect_uri = uri.clone();
+        let connect: Connector = Arc::from(connect);
+        let endpoints = Endpoints::new(uris, &options);
+        let (connect_promise, resolver) =
+            pinky_swear::PinkySwear::<(Endpoints, Result<(TcpStream, AMQPUri)>)>::new();
         executor.spawn({
             let executor = executor.clone();
+            let connect = connect.clone();
             Box::pin(async move {
                 executor
                     .spawn_blocking(Box::new(move || {
-                        let mut res = connect(&connect_uri);
-                        loop {
-                            match res {
-                                Ok(stream) => {
-                                    resolver.swear(Ok(stream));
-                                    break;
-                                }
-                                Err(mid) => match mid.into_mid_handshake_tls_stream() {
-                                    Err(err) => {
-                                        resolver.swear(Err(err.into()));
-                                        break;
-                                    }
-                                    Ok(mid) => {
-                                        res = mid.handshake();
-                                    }
-                                },
-                            }
-                        }
+                        let mut endpoints = endpoints;
+                        let res = endpoints.connect(&*connect);
+                        resolver.swear((endpoints, res));
                     }))
                     .await;
             })
@@ -336,15 +515,17 @@ impl Connection {
         let internal_rpc = InternalRPC::new(executor.clone(), waker.clone());
         let frames = Frames::default();
         let conn = Connection::new(
-            waker,
+            waker.clone(),
             internal_rpc.handle(),
             frames.clone(),
             executor.clone(),
+            reactor.clone(),
         );
         let status = conn.status.clone();
         let configuration = conn.configuration.clone();
-        status.set_vhost(&uri.vhost);
-        status.set_username(&uri.authority.userinfo.username);
+        let (endpoints, res) = connect_promise.await;
+        let (stream, uri) = res?;
+        let credentials = fetch_credentials(&uri, &options).await?;
         if let Some(frame_max) = uri.query.frame_max {
             configuration.set_frame_max(frame_max);
         }
@@ -354,12 +535,84 @@ impl Connection {
         if let Some(heartbeat) = uri.query.heartbeat {
             configuration.set_heartbeat(heartbeat);
         }
+        configuration.set_publish_backpressure(options.publish_backpressure);
+        configuration.set_consumer_buffer_limits(options.consumer_buffer_limits);
+        configuration.set_connection_buffer_limits(options.connection_buffer_limits);
+        configuration.set_buffer_overflow_policy(options.buffer_overflow_policy);
+        let channels = conn.channels.clone();
+        let io_loop_handle = conn.io_loop.clone();
+        let secret_refresh = SecretRefresh::new(&options, executor.clone(), reactor.clone());
+        let recovery = options.recovery_config.clone().map(|config| {
+            Recovery::new(
+                config,
+                endpoints,
+                uri.clone(),
+                connect,
+                options.clone(),
+                conn.downgrade(),
+                secret_refresh.clone(),
+                executor.clone(),
+                reactor.clone(),
+            )
+        });
+        let amqp_handshake_timeout = options.amqp_handshake_timeout;
+        let (promise_out, promise_in) =
+            conn.start_handshake(&uri, credentials.credentials, options);
+        let stream = reactor.register(IOHandle::new(stream))?.into();
+        let heartbeat = Heartbeat::new(
+            channels.clone(),
+            executor.clone(),
+            reactor.clone(),
+            waker.clone(),
+        );
+        let internal_rpc_handle = internal_rpc.handle();
+        executor.spawn(Box::pin(internal_rpc.run(channels.clone())));
+        IoLoop::new(
+            status.clone(),
+            configuration,
+            channels.clone(),
+            internal_rpc_handle,
+            frames,
+            socket_state,
+            io_loop_handle,
+            stream,
+            heartbeat,
+            recovery,
+        )
+        .await
+        .and_then(IoLoop::start)?;
+        let res = wait_handshake(
+            promise_out,
+            promise_in,
+            amqp_handshake_timeout,
+            reactor.clone(),
+        )
+        .await;
+        if let Err(error @ Error::ConnectionTimeout(_)) = &res {
+            // Give up on the handshake and let the io loop shut the socket down
+            if let Some(resolver) = status.connection_resolver() {
+                resolver.swear(Err(error.clone()));
+            }
+            channels.set_connection_error(error.clone());
+            waker.wake();
+        }
+        let conn = res?;
+        secret_refresh.start(conn.downgrade(), credentials.expires_in);
+        Ok(conn)
+    }
+
+    /// Send the protocol header and prepare the handshake state machine
+    pub(crate) fn start_handshake(
+        self,
+        uri: &AMQPUri,
+        credentials: Credentials,
+        options: ConnectionProperties,
+    ) -> (Promise<()>, Promise<Connection>) {
         let (promise_out, resolver) = Promise::new();
         if level_enabled!(Level::TRACE) {
             promise_out.set_marker("ProtocolHeader".into());
         }
-        let channels = conn.channels.clone();
-        if let Some(channel0) = channels.get(0) {
+        if let Some(channel0) = self.channels.get(0) {
             channel0.send_frame(
                 AMQPFrame::ProtocolHeader(ProtocolVersion::amqp_0_9_1()),
                 resolver,
@@ -370,35 +623,35 @@ impl Connection {
         if level_enabled!(Level::TRACE) {
             promise_in.set_marker("ProtocolHeader.Ok".into());
         }
-        let io_loop_handle = conn.io_loop.clone();
+        let status = self.status.clone();
+        status.set_vhost(&uri.vhost);
+        status.set_username(&uri.authority.userinfo.username);
+        status.set_endpoint(uri);
         status.set_state(ConnectionState::Connecting);
+        status.emit(Event::Connecting);
         status.set_connection_step(ConnectionStep::ProtocolHeader(
             resolver,
-            conn,
-            uri.authority.userinfo.into(),
-            uri.query.auth_mechanism.unwrap_or_default(),
+            self,
+            credentials,
+            if options.sasl_mechanisms.is_empty() {
+                vec![Arc::new(uri.query.auth_mechanism.unwrap_or_default())]
+            } else {
+                options.sasl_mechanisms.clone()
+            },
             options,
         ));
-        let stream = connect_promise.await?;
-        let stream = reactor.register(IOHandle::new(stream))?.into();
-        let heartbeat = Heartbeat::new(channels.clone(), executor.clone(), reactor);
-        let internal_rpc_handle = internal_rpc.handle();
-        executor.spawn(Box::pin(internal_rpc.run(channels.clone())));
-        IoLoop::new(
-            status,
-            configuration,
-            channels,
-            internal_rpc_handle,
-            frames,
-            socket_state,
-            io_loop_handle,
-            stream,
-            heartbeat,
-        )
-        .await
-        .and_then(IoLoop::start)?;
-        promise_out.await?;
-        promise_in.await
+        (promise_out, promise_in)
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakConnection {
+        WeakConnection {
+            configuration: self.configuration.clone(),
+            status: self.status.clone(),
+            global_registry: self.global_registry.clone(),
+            channels: self.channels.clone(),
+            io_loop: self.io_loop.clone(),
+            closer: Arc::downgrade(&self.closer),
+        }
     }
 
     /// Get the current topology
@@ -427,6 +680,150 @@ impl fmt::Debug for Connection {
     }
 }
 
+/// A handle on a Connection which doesn't prevent it from being closed when dropped
+pub(crate) struct WeakConnection {
+    configuration: Configuration,
+    status: ConnectionStatus,
+    global_registry: Registry,
+    channels: Channels,
+    io_loop: ThreadHandle,
+    closer: Weak<ConnectionCloser>,
+}
+
+impl WeakConnection {
+    pub(crate) fn is_alive(&self) -> bool {
+        self.closer.strong_count() > 0
+    }
+
+    pub(crate) fn upgrade(&self) -> Option<Connection> {
+        Some(Connection {
+            configuration: self.configuration.clone(),
+            status: self.status.clone(),
+            global_registry: self.global_registry.clone(),
+            channels: self.channels.clone(),
+            io_loop: self.io_loop.clone(),
+            closer: self.closer.upgrade()?,
+        })
+    }
+}
+
+/// Wait for the AMQP handshake started by `Connection::start_handshake` to complete, failing
+/// with a `ConnectionTimeout` if it takes longer than `timeout`
+pub(crate) async fn wait_handshake(
+    promise_out: Promise<()>,
+    promise_in: Promise<Connection>,
+    timeout: Option<Duration>,
+    reactor: Arc<dyn Reactor + Send + Sync>,
+) -> Result<Connection> {
+    let handshake = async move {
+        promise_out.await?;
+        promise_in.await
+    };
+    match timeout {
+        Some(timeout) => {
+            future::or(handshake, async move {
+                reactor.sleep(timeout).await;
+                Err(Error::ConnectionTimeout(ConnectionPhase::AmqpHandshake))
+            })
+            .await
+        }
+        None => handshake.await,
+    }
+}
+
+pub(crate) fn connect_stream(
+    connect: &(dyn Fn(&AMQPUri) -> HandshakeResult + Send + Sync),
+    uri: &AMQPUri,
+    tls_handshake_timeout: Option<Duration>,
+) -> Result<TcpStream> {
+    let mut res = match connect(uri) {
+        Err(HandshakeError::Failure(err)) if err.kind() == io::ErrorKind::TimedOut => {
+            return Err(Error::ConnectionTimeout(ConnectionPhase::TcpConnect));
+        }
+        res => res,
+    };
+    let deadline = tls_handshake_timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        match res {
+            Ok(stream) => {
+                // The handshake may have been performed on a blocking socket with timeouts
+                stream.set_read_timeout(None)?;
+                stream.set_write_timeout(None)?;
+                stream.set_nonblocking(true)?;
+                return Ok(stream);
+            }
+            Err(mid) => match mid.into_mid_handshake_tls_stream() {
+                Err(err) => return Err(err.into()),
+                Ok(mid) => {
+                    if let Some(deadline) = deadline {
+                        match deadline.checked_duration_since(Instant::now()) {
+                            Some(remaining) if remaining > Duration::from_secs(0) => {
+                                mid.get_ref().set_read_timeout(Some(remaining))?;
+                                mid.get_ref().set_write_timeout(Some(remaining))?;
+                            }
+                            _ => {
+                                return Err(Error::ConnectionTimeout(ConnectionPhase::TlsHandshake))
+                            }
+                        }
+                    }
+                    res = mid.handshake();
+                }
+            },
+        }
+    }
+}
+
+/// Connect to `uri` using `AMQPUriTcpExt::connect_with_config`, without blocking for more than
+/// `tls_handshake_timeout` on each step of the TLS handshake.
+#[allow(clippy::result_large_err)]
+fn connect_uri(
+    uri: &AMQPUri,
+    config: TLSConfig<'_, '_, '_>,
+    tls_handshake_timeout: Option<Duration>,
+) -> HandshakeResult {
+    let timeout = match (&uri.scheme, tls_handshake_timeout) {
+        (&AMQPScheme::AMQPS, Some(timeout)) => timeout,
+        _ => return uri.connect_with_config(config),
+    };
+    // Open the TCP connection the usual way, then perform the TLS handshake on a socket which
+    // doesn't block forever. connect_stream takes care of the handshake if it gets interrupted.
+    let mut plain = uri.clone();
+    plain.scheme = AMQPScheme::AMQP;
+    let stream = plain.connect_with_config(TLSConfig::default())?;
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.into_tls(&uri.authority.host, config)
+}
+
+/// Read the timeouts which are handled by lapin itself from the query of `uri`.
+///
+/// Like `connection_timeout`, they are expressed in milliseconds and take precedence over the
+/// ones set in the `ConnectionProperties`.
+fn parse_uri_timeouts(uri: &str, options: &mut ConnectionProperties) -> Result<()> {
+    let query = match uri.find('?') {
+        Some(index) => &uri[index + 1..],
+        None => return Ok(()),
+    };
+    for param in query.split('&') {
+        let mut param = param.splitn(2, '=');
+        let (key, value) = match (param.next(), param.next()) {
+            (Some(key), Some(value)) => (key, value),
+            _ => continue,
+        };
+        let timeout = match key {
+            "tls_handshake_timeout" => &mut options.tls_handshake_timeout,
+            "amqp_handshake_timeout" => &mut options.amqp_handshake_timeout,
+            _ => continue,
+        };
+        let millis = value
+            .parse::<u64>()
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        *timeout = Some(Duration::from_millis(millis));
+    }
+    Ok(())
+}
+
 /// Trait providing a method to connect to an AMQP server
 #[async_trait]
 pub trait Connect {
@@ -445,9 +842,36 @@ impl Connect for AMQPUri {
         options: ConnectionProperties,
         config: OwnedTLSConfig,
     ) -> Result<Connection> {
-        Connection::connector(
+        Connect::connect(vec![self], options, config).await
+    }
+}
+
+#[async_trait]
+impl Connect for &str {
+    async fn connect(
+        self,
+        mut options: ConnectionProperties,
+        config: OwnedTLSConfig,
+    ) -> Result<Connection> {
+        parse_uri_timeouts(self, &mut options)?;
+        match self.parse::<AMQPUri>() {
+            Ok(uri) => Connect::connect(uri, options, config).await,
+            Err(err) => Err(io::Error::new(io::ErrorKind::Other, err).into()),
+        }
+    }
+}
+
+#[async_trait]
+impl Connect for Vec<AMQPUri> {
+    async fn connect(
+        self,
+        options: ConnectionProperties,
+        config: OwnedTLSConfig,
+    ) -> Result<Connection> {
+        let tls_handshake_timeout = options.tls_handshake_timeout;
+        Connection::connector_with_endpoints(
             self,
-            Box::new(move |uri| AMQPUriTcpExt::connect_with_config(uri, config.as_ref())),
+            Box::new(move |uri| connect_uri(uri, config.as_ref(), tls_handshake_timeout)),
             options,
         )
         .await
@@ -455,14 +879,21 @@ impl Connect for AMQPUri {
 }
 
 #[async_trait]
-impl Connect for &str {
+impl Connect for &[&str] {
     async fn connect(
         self,
-        options: ConnectionProperties,
+        mut options: ConnectionProperties,
         config: OwnedTLSConfig,
     ) -> Result<Connection> {
-        match self.parse::<AMQPUri>() {
-            Ok(uri) => Connect::connect(uri, options, config).await,
+        for uri in self {
+            parse_uri_timeouts(uri, &mut options)?;
+        }
+        match self
+            .iter()
+            .map(|uri| uri.parse::<AMQPUri>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+        {
+            Ok(uris) => Connect::connect(uris, options, config).await,
             Err(err) => Err(io::Error::new(io::ErrorKind::Other, err).into()),
         }
     }
@@ -474,7 +905,8 @@ mod tests {
     use crate::channel_receiver_state::{ChannelReceiverState, DeliveryCause};
     use crate::channel_status::ChannelState;
     use crate::options::BasicConsumeOptions;
-    use crate::types::{FieldTable, ShortString};
+    use crate::publisher_confirm::{Confirmation, PublisherConfirm};
+    use crate::types::{DeliveryTag, FieldTable, ShortString};
     use crate::BasicProperties;
     use amq_protocol::frame::AMQPContentHeader;
     use amq_protocol::protocol::{basic, AMQPClass};
@@ -495,6 +927,7 @@ mod tests {
             internal_rpc.handle(),
             Frames::default(),
             executor.clone(),
+            Arc::new(async_reactor_trait::AsyncIo),
         );
         conn.status.set_state(ConnectionState::Connected);
         conn.configuration.set_channel_max(2047);
@@ -574,6 +1007,7 @@ mod tests {
             internal_rpc.handle(),
             Frames::default(),
             executor.clone(),
+            Arc::new(async_reactor_trait::AsyncIo),
         );
         conn.status.set_state(ConnectionState::Connected);
         conn.configuration.set_channel_max(2047);
@@ -628,4 +1062,1764 @@ mod tests {
             assert_eq!(channel_state, expected_state);
         }
     }
+
+    #[test]
+    fn uri_timeouts() {
+        let mut options =
+            ConnectionProperties::default().with_tls_handshake_timeout(Duration::from_secs(10));
+        parse_uri_timeouts(
+            "amqps://127.0.0.1/%2f?heartbeat=10&amqp_handshake_timeout=5000",
+            &mut options,
+        )
+        .unwrap();
+        assert_eq!(options.tls_handshake_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(options.amqp_handshake_timeout, Some(Duration::from_secs(5)));
+        parse_uri_timeouts(
+            "amqps://127.0.0.1/%2f?tls_handshake_timeout=200",
+            &mut options,
+        )
+        .unwrap();
+        assert_eq!(
+            options.tls_handshake_timeout,
+            Some(Duration::from_millis(200))
+        );
+        assert!(
+            parse_uri_timeouts("amqp://127.0.0.1?amqp_handshake_timeout=soon", &mut options)
+                .is_err()
+        );
+    }
+
+    fn accept(listener: &std::net::TcpListener) -> std::net::TcpStream {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false).unwrap();
+                    stream
+                        .set_read_timeout(Some(Duration::from_secs(10)))
+                        .unwrap();
+                    return stream;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    assert!(Instant::now() < deadline, "no connection received");
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(err) => panic!("accept failed: {}", err),
+            }
+        }
+    }
+
+    fn recovery_options() -> ConnectionProperties {
+        ConnectionProperties::default().with_recovery_config(crate::RecoveryConfig {
+            initial_delay: Duration::from_millis(10),
+            ..Default::default()
+        })
+    }
+
+    /// Start an io loop connected to `listener` as if the handshake was done, with recovery
+    /// enabled if `options` has a recovery config. Returns the connection and the server side of
+    /// the socket.
+    #[allow(clippy::result_large_err)]
+    fn running_connection(
+        listener: &std::net::TcpListener,
+        heartbeat: u16,
+        options: ConnectionProperties,
+    ) -> (Connection, std::net::TcpStream) {
+        use crate::tcp::AMQPUriTcpExt;
+
+        let executor: Arc<dyn FullExecutor + Send + Sync> =
+            Arc::new(async_global_executor_trait::AsyncGlobalExecutor);
+        let reactor: Arc<dyn Reactor + Send + Sync> = Arc::new(async_reactor_trait::AsyncIo);
+        let socket_state = SocketState::default();
+        let waker = socket_state.handle();
+        let internal_rpc = InternalRPC::new(executor.clone(), waker.clone());
+        let frames = Frames::default();
+        let conn = Connection::new(
+            waker.clone(),
+            internal_rpc.handle(),
+            frames.clone(),
+            executor.clone(),
+            reactor.clone(),
+        );
+        conn.status.set_state(ConnectionState::Connected);
+        conn.configuration.set_heartbeat(heartbeat);
+
+        let uri: AMQPUri = format!("amqp://127.0.0.1:{}", listener.local_addr().unwrap().port())
+            .parse()
+            .unwrap();
+        let connect: Connector = Arc::new(|uri: &AMQPUri| uri.connect());
+        let (stream, uri) = Endpoints::new(vec![uri], &options)
+            .connect(&*connect)
+            .unwrap();
+        let server = accept(listener);
+        let recovery = options.recovery_config.clone().map(|config| {
+            Recovery::new(
+                config,
+                Endpoints::new(vec![uri.clone()], &options),
+                uri,
+                connect,
+                options.clone(),
+                conn.downgrade(),
+                SecretRefresh::new(&options, executor.clone(), reactor.clone()),
+                executor.clone(),
+                reactor.clone(),
+            )
+        });
+        let stream = reactor.register(IOHandle::new(stream)).unwrap().into();
+        let heartbeat = Heartbeat::new(conn.channels.clone(), executor.clone(), reactor, waker);
+        let internal_rpc_handle = internal_rpc.handle();
+        executor.spawn(Box::pin(internal_rpc.run(conn.channels.clone())));
+        future::block_on(IoLoop::new(
+            conn.status.clone(),
+            conn.configuration.clone(),
+            conn.channels.clone(),
+            internal_rpc_handle,
+            frames,
+            socket_state,
+            conn.io_loop.clone(),
+            stream,
+            heartbeat,
+            recovery,
+        ))
+        .and_then(IoLoop::start)
+        .unwrap();
+        (conn, server)
+    }
+
+    /// Wait for the io loop to start the handshake on a new socket, which shows it went through
+    /// recovery and kept running. Returns the server side of the new socket.
+    fn assert_reconnects(
+        listener: &std::net::TcpListener,
+        events: &mut EventStream,
+        error: fn(&Error) -> bool,
+    ) -> std::net::TcpStream {
+        use futures_lite::StreamExt;
+        use std::io::Read;
+
+        let mut server = accept(listener);
+        loop {
+            match future::block_on(events.next()) {
+                Some(Event::Reconnecting(err)) => {
+                    assert!(error(&err), "unexpected error: {}", err);
+                    break;
+                }
+                // Emitted when starting the handshake of a previous attempt
+                Some(Event::Connecting) => {}
+                event => panic!("unexpected event: {:?}", event),
+            }
+        }
+        let mut header = [0; 8];
+        server.read_exact(&mut header).unwrap();
+        assert_eq!(&header, b"AMQP\x00\x00\x09\x01");
+        server
+    }
+
+    #[test]
+    fn recovery_after_io_error() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let (conn, server) = running_connection(&listener, 0, recovery_options());
+        let mut events = conn.events();
+
+        // The server going away makes reading fail, the io loop has to reconnect
+        drop(server);
+        assert_reconnects(&listener, &mut events, |error| {
+            matches!(error, Error::IOError(_))
+        });
+    }
+
+    #[test]
+    fn recovery_after_missed_heartbeat() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let (conn, _server) = running_connection(&listener, 1, recovery_options());
+        let mut events = conn.events();
+
+        // The server stays connected but never sends anything
+        assert_reconnects(&listener, &mut events, |error| {
+            matches!(error, Error::MissingHeartbeatError)
+        });
+    }
+
+    #[test]
+    fn missed_heartbeat_without_recovery() {
+        use crate::{consumer::Consumer, options::BasicQosOptions};
+        use futures_lite::StreamExt;
+        use parking_lot::Mutex;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let (conn, _server) = running_connection(&listener, 1, ConnectionProperties::default());
+        let mut events = conn.events();
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let handler_errors = errors.clone();
+        conn.on_error(move |error| handler_errors.lock().push(error));
+        conn.configuration.set_channel_max(2047);
+        let channel = conn.channels.create(conn.closer.clone()).unwrap();
+        channel.set_state(ChannelState::Connected);
+        let consumer_tag = ShortString::from("consumer-tag");
+        let mut consumer = Consumer::new(
+            consumer_tag.clone(),
+            Arc::new(async_global_executor_trait::AsyncGlobalExecutor),
+            None,
+            "queue".into(),
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        );
+        channel.register_consumer(consumer_tag, consumer.clone());
+        // The server never answers, so this only completes when the connection fails
+        let mut qos = Box::pin(channel.basic_qos(1, BasicQosOptions::default()));
+        assert!(future::block_on(future::poll_once(&mut qos)).is_none());
+
+        // The server stays connected but never sends anything
+        assert!(matches!(
+            future::block_on(consumer.next()),
+            Some(Err(Error::MissingHeartbeatError))
+        ));
+        assert!(matches!(
+            future::block_on(qos),
+            Err(Error::MissingHeartbeatError)
+        ));
+        assert!(emitted(&mut events)
+            .iter()
+            .any(|event| matches!(event, Event::ConnectionError(Error::MissingHeartbeatError))));
+        assert_eq!(channel.status().state(), ChannelState::Error);
+        assert!(matches!(
+            errors.lock().as_slice(),
+            [Error::MissingHeartbeatError]
+        ));
+    }
+
+    #[test]
+    fn recovery_after_handshake_timeout() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let options = recovery_options().with_amqp_handshake_timeout(Duration::from_millis(100));
+        let (conn, server) = running_connection(&listener, 0, options);
+        let mut events = conn.events();
+
+        drop(server);
+        // The new server accepts the connection but never answers the protocol header
+        let _stalled = assert_reconnects(&listener, &mut events, |error| {
+            matches!(error, Error::IOError(_))
+        });
+        assert_reconnects(&listener, &mut events, |error| {
+            matches!(
+                error,
+                Error::ConnectionTimeout(ConnectionPhase::AmqpHandshake)
+            )
+        });
+    }
+
+    struct CountingProvider(std::sync::atomic::AtomicUsize);
+
+    #[async_trait]
+    impl crate::CredentialsProvider for CountingProvider {
+        async fn credentials(&self) -> Result<crate::ExpiringCredentials> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(crate::ExpiringCredentials {
+                credentials: Credentials::default(),
+                expires_in: None,
+            })
+        }
+    }
+
+    fn secret_refresh() -> (Connection, SecretRefresh, Arc<CountingProvider>) {
+        let executor: Arc<dyn FullExecutor + Send + Sync> =
+            Arc::new(async_global_executor_trait::AsyncGlobalExecutor);
+        let reactor: Arc<dyn Reactor + Send + Sync> = Arc::new(async_reactor_trait::AsyncIo);
+        let socket_state = SocketState::default();
+        let waker = socket_state.handle();
+        let internal_rpc = InternalRPC::new(executor.clone(), waker.clone());
+        let conn = Connection::new(
+            waker,
+            internal_rpc.handle(),
+            Frames::default(),
+            executor.clone(),
+            reactor.clone(),
+        );
+        conn.status().set_state(ConnectionState::Connected);
+        let provider = Arc::new(CountingProvider(Default::default()));
+        let options = ConnectionProperties {
+            credentials_provider: Some(provider.clone()),
+            ..Default::default()
+        };
+        let refresh = SecretRefresh::new(&options, executor, reactor);
+        (conn, refresh, provider)
+    }
+
+    fn wait_for_refresh(provider: &CountingProvider) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(3);
+        while Instant::now() < deadline {
+            if provider.0.load(std::sync::atomic::Ordering::SeqCst) > 0 {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn refresh_from_recovered_credentials() {
+        let (conn, refresh, provider) = secret_refresh();
+        // The credentials of the initial handshake never expire, the recovered ones do
+        refresh.start(conn.downgrade(), None);
+        refresh.start(conn.downgrade(), Some(Duration::from_secs(1)));
+        assert!(wait_for_refresh(&provider));
+    }
+
+    #[test]
+    fn refresh_replaced_by_later_handshake() {
+        let (conn, refresh, provider) = secret_refresh();
+        refresh.start(conn.downgrade(), Some(Duration::from_secs(1)));
+        // The credentials of the recovery handshake never expire
+        refresh.start(conn.downgrade(), None);
+        assert!(!wait_for_refresh(&provider));
+    }
+
+    #[test]
+    fn coalesced_acks() {
+        use crate::{options::BasicAckOptions, AckBatching};
+        use futures_lite::future;
+        use std::time::Duration;
+
+        let executor = Arc::new(async_global_executor_trait::AsyncGlobalExecutor);
+        let socket_state = SocketState::default();
+        let waker = socket_state.handle();
+        let internal_rpc = InternalRPC::new(executor.clone(), waker.clone());
+        let frames = Frames::default();
+        let conn = Connection::new(
+            waker,
+            internal_rpc.handle(),
+            frames.clone(),
+            executor,
+            Arc::new(async_reactor_trait::AsyncIo),
+        );
+        conn.status.set_state(ConnectionState::Connected);
+        conn.configuration.set_channel_max(2047);
+        let channel = conn.channels.create(conn.closer.clone()).unwrap();
+        channel.set_state(ChannelState::Connected);
+        channel.set_ack_batching(AckBatching {
+            max_messages: 3,
+            max_delay: Duration::from_secs(3600),
+        });
+        for delivery_tag in 1..=3 {
+            channel.status().register_delivery(delivery_tag);
+        }
+
+        // Ack the deliveries out of order, nothing gets sent until the batch is full
+        let mut acks = [3, 1, 2]
+            .iter()
+            .map(|delivery_tag| {
+                let channel = channel.clone();
+                Box::pin(async move {
+                    channel
+                        .ack_delivery(*delivery_tag, BasicAckOptions::default())
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+        for ack in acks.iter_mut() {
+            assert!(future::block_on(future::poll_once(ack)).is_none());
+        }
+
+        let (frame, resolver) = frames.pop(true).expect("no frame sent");
+        match frame {
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Ack(ack))) => {
+                assert_eq!(ack.delivery_tag, 3);
+                assert!(ack.multiple);
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert!(frames.pop(true).is_none());
+        assert!(channel.status().unacked_deliveries().is_empty());
+
+        // Each ack resolves once the frame got written
+        resolver.expect("no resolver").swear(Ok(()));
+        let results = future::block_on(async move {
+            let mut results = Vec::new();
+            // The last ack is the one flushing the batch, drive it first
+            for ack in acks.into_iter().rev() {
+                results.push(ack.await);
+            }
+            results
+        });
+        assert_eq!(results, vec![Ok(()), Ok(()), Ok(())]);
+    }
+
+    #[test]
+    fn coalesced_acks_after_recovery() {
+        use crate::{options::BasicAckOptions, AckBatching};
+        use std::time::{Duration, Instant};
+
+        let (_conn, channel, frames) = connected_channel();
+        // The flush scheduled before the recovery won't happen in time
+        channel.set_ack_batching(AckBatching::default().with_max_delay(Duration::from_secs(3600)));
+        channel.status().register_delivery(1);
+        let mut ack = Box::pin(channel.ack_delivery(1, BasicAckOptions::default()));
+        assert!(future::block_on(future::poll_once(&mut ack)).is_none());
+
+        // The delivery tags of the previous channel are no longer valid
+        let error = Error::MissingHeartbeatError;
+        channel.set_recovering(error.clone());
+        assert_eq!(future::block_on(ack), Err(error));
+        assert!(frames.pop(true).is_none());
+
+        channel.set_state(ChannelState::Connected);
+        channel.set_ack_batching(AckBatching::default().with_max_delay(Duration::from_millis(1)));
+        channel.status().register_delivery(1);
+        let mut ack = Box::pin(channel.ack_delivery(1, BasicAckOptions::default()));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(res) = future::block_on(future::poll_once(&mut ack)) {
+                assert_eq!(res, Ok(()));
+                break;
+            }
+            if let Some((frame, resolver)) = frames.pop(true) {
+                match frame {
+                    AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Ack(ack))) => {
+                        assert_eq!(ack.delivery_tag, 1);
+                        assert!(!ack.multiple);
+                    }
+                    frame => panic!("unexpected frame: {:?}", frame),
+                }
+                resolver.expect("no resolver").swear(Ok(()));
+            }
+            assert!(Instant::now() < deadline, "ack not flushed");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(channel.status().unacked_deliveries().is_empty());
+    }
+
+    fn connected_channel() -> (Connection, Channel, Frames) {
+        new_channel(false)
+    }
+
+    /// Like `connected_channel`, running the internal RPC commands, such as the acks of the
+    /// deliveries
+    fn consuming_channel() -> (Connection, Channel, Frames) {
+        new_channel(true)
+    }
+
+    fn new_channel(run_internal_rpc: bool) -> (Connection, Channel, Frames) {
+        let executor: Arc<dyn FullExecutor + Send + Sync> =
+            Arc::new(async_global_executor_trait::AsyncGlobalExecutor);
+        let socket_state = SocketState::default();
+        let waker = socket_state.handle();
+        let internal_rpc = InternalRPC::new(executor.clone(), waker.clone());
+        let frames = Frames::default();
+        let conn = Connection::new(
+            waker,
+            internal_rpc.handle(),
+            frames.clone(),
+            executor.clone(),
+            Arc::new(async_reactor_trait::AsyncIo),
+        );
+        conn.status.set_state(ConnectionState::Connected);
+        conn.configuration.set_channel_max(2047);
+        let channel = conn.channels.create(conn.closer.clone()).unwrap();
+        channel.set_state(ChannelState::Connected);
+        if run_internal_rpc {
+            executor.spawn(Box::pin(internal_rpc.run(conn.channels.clone())));
+        }
+        (conn, channel, frames)
+    }
+
+    /// Drive `transaction`, answering the tx methods it sends like the server would.
+    /// Returns its output along with the names of these methods.
+    fn run_transaction<T>(
+        conn: &Connection,
+        frames: &Frames,
+        sent: &std::cell::RefCell<Vec<&'static str>>,
+        transaction: impl std::future::Future<Output = T>,
+    ) -> T {
+        use amq_protocol::protocol::tx;
+        use std::time::{Duration, Instant};
+
+        let mut transaction = Box::pin(transaction);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(output) = future::block_on(future::poll_once(&mut transaction)) {
+                return output;
+            }
+            while let Some((frame, resolver)) = frames.pop(true) {
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                let (channel_id, method) = match frame {
+                    AMQPFrame::Method(channel_id, AMQPClass::Tx(method)) => (channel_id, method),
+                    frame => panic!("unexpected frame: {:?}", frame),
+                };
+                let (name, reply) = match method {
+                    tx::AMQPMethod::Select(_) => {
+                        ("select", tx::AMQPMethod::SelectOk(tx::SelectOk {}))
+                    }
+                    tx::AMQPMethod::Commit(_) => {
+                        ("commit", tx::AMQPMethod::CommitOk(tx::CommitOk {}))
+                    }
+                    tx::AMQPMethod::Rollback(_) => {
+                        ("rollback", tx::AMQPMethod::RollbackOk(tx::RollbackOk {}))
+                    }
+                    method => panic!("unexpected method: {:?}", method),
+                };
+                sent.borrow_mut().push(name);
+                conn.channels
+                    .handle_frame(AMQPFrame::Method(channel_id, AMQPClass::Tx(reply)))
+                    .unwrap();
+            }
+            assert!(Instant::now() < deadline, "transaction didn't complete");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn transaction_commit() {
+        let (conn, channel, frames) = connected_channel();
+        let sent = std::cell::RefCell::default();
+
+        let res = run_transaction(
+            &conn,
+            &frames,
+            &sent,
+            channel.transaction(|_| async { Ok::<_, Error>(42) }),
+        );
+        assert_eq!(res, Ok(42));
+        assert!(channel.status().transactional());
+        // The channel stays transactional
+        let res = run_transaction(
+            &conn,
+            &frames,
+            &sent,
+            channel.transaction(|_| async { Ok::<_, Error>(()) }),
+        );
+        assert_eq!(res, Ok(()));
+        assert_eq!(*sent.borrow(), vec!["select", "commit", "commit"]);
+    }
+
+    #[test]
+    fn transaction_rollback_on_error() {
+        let (conn, channel, frames) = connected_channel();
+        let sent = std::cell::RefCell::default();
+
+        let res = run_transaction(
+            &conn,
+            &frames,
+            &sent,
+            channel.transaction(|_| async { Err::<(), _>(Error::ChannelsLimitReached) }),
+        );
+        assert_eq!(res, Err(Error::ChannelsLimitReached));
+        assert_eq!(*sent.borrow(), vec!["select", "rollback"]);
+    }
+
+    #[test]
+    fn transaction_rollback_on_panic() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let (conn, channel, frames) = connected_channel();
+        let sent = std::cell::RefCell::default();
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            run_transaction(
+                &conn,
+                &frames,
+                &sent,
+                channel.transaction::<_, _, (), Error>(|_| async {
+                    panic!("transaction body panicked")
+                }),
+            )
+        }));
+        assert!(res.is_err(), "the panic wasn't propagated");
+        assert_eq!(*sent.borrow(), vec!["select", "rollback"]);
+    }
+
+    #[test]
+    fn transaction_in_confirm_mode() {
+        let (conn, channel, frames) = connected_channel();
+        let sent = std::cell::RefCell::default();
+        channel.status().set_confirm();
+
+        let res = run_transaction(
+            &conn,
+            &frames,
+            &sent,
+            channel.transaction(|_| async { Ok::<_, Error>(()) }),
+        );
+        assert_eq!(res, Err(Error::ChannelInConfirmMode(channel.id())));
+        assert!(sent.borrow().is_empty());
+    }
+
+    /// The events emitted so far
+    fn emitted(events: &mut EventStream) -> Vec<Event> {
+        use futures_lite::StreamExt;
+
+        std::iter::from_fn(|| future::block_on(future::poll_once(events.next())).flatten())
+            .collect()
+    }
+
+    #[test]
+    fn close_event_on_close_ok() {
+        use amq_protocol::protocol::connection;
+
+        let (conn, _channel, frames) = connected_channel();
+        let mut events = conn.events();
+        let mut close = Box::pin(conn.close(200, "bye"));
+        assert!(future::block_on(future::poll_once(&mut close)).is_none());
+
+        let (frame, resolver) = frames.pop(true).expect("no frame sent");
+        assert!(matches!(
+            frame,
+            AMQPFrame::Method(0, AMQPClass::Connection(connection::AMQPMethod::Close(_)))
+        ));
+        resolver.expect("no resolver").swear(Ok(()));
+        assert!(future::block_on(future::poll_once(&mut close)).is_none());
+        assert!(!emitted(&mut events)
+            .iter()
+            .any(|event| matches!(event, Event::ConnectionClosed { .. })));
+
+        conn.channels
+            .handle_frame(AMQPFrame::Method(
+                0,
+                AMQPClass::Connection(connection::AMQPMethod::CloseOk(connection::CloseOk {})),
+            ))
+            .unwrap();
+        assert_eq!(future::block_on(close), Ok(()));
+        assert_eq!(
+            emitted(&mut events),
+            vec![Event::ConnectionClosed {
+                reply_code: 200,
+                reply_text: "bye".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn close_event_while_reconnecting() {
+        let (conn, _channel, frames) = connected_channel();
+        let mut events = conn.events();
+        conn.status.set_state(ConnectionState::Reconnecting);
+
+        assert_eq!(future::block_on(conn.close(200, "bye")), Ok(()));
+        assert!(frames.pop(true).is_none());
+        assert_eq!(
+            emitted(&mut events),
+            vec![Event::ConnectionClosed {
+                reply_code: 200,
+                reply_text: "bye".into(),
+            }]
+        );
+    }
+
+    fn channel_closed_events(events: &mut EventStream) -> Vec<Event> {
+        emitted(events)
+            .into_iter()
+            .filter(|event| matches!(event, Event::ChannelClosed { .. }))
+            .collect()
+    }
+
+    #[test]
+    fn channel_close_event_on_close_ok() {
+        use amq_protocol::protocol::channel;
+
+        let (conn, channel, frames) = connected_channel();
+        let mut events = conn.events();
+        let mut close = Box::pin(channel.close(200, "bye"));
+        assert!(future::block_on(future::poll_once(&mut close)).is_none());
+
+        let (frame, resolver) = frames.pop(true).expect("no frame sent");
+        assert!(matches!(
+            frame,
+            AMQPFrame::Method(_, AMQPClass::Channel(channel::AMQPMethod::Close(_)))
+        ));
+        resolver.expect("no resolver").swear(Ok(()));
+        assert!(future::block_on(future::poll_once(&mut close)).is_none());
+        assert!(channel_closed_events(&mut events).is_empty());
+
+        conn.channels
+            .handle_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Channel(channel::AMQPMethod::CloseOk(channel::CloseOk {})),
+            ))
+            .unwrap();
+        assert_eq!(future::block_on(close), Ok(()));
+        assert_eq!(
+            channel_closed_events(&mut events),
+            vec![Event::ChannelClosed {
+                channel_id: channel.id(),
+                reply_code: 200,
+                reply_text: "bye".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn channel_close_event_on_server_close() {
+        use amq_protocol::protocol::channel;
+        use std::time::{Duration, Instant};
+
+        let (conn, channel, frames) = connected_channel();
+        let mut events = conn.events();
+        conn.channels
+            .handle_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                    reply_code: 404,
+                    reply_text: "NOT_FOUND".into(),
+                    class_id: 50,
+                    method_id: 10,
+                })),
+            ))
+            .unwrap();
+
+        // We answer with a close-ok, after which the channel is closed
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let frame = loop {
+            if let Some((frame, _)) = frames.pop(true) {
+                break frame;
+            }
+            assert!(Instant::now() < deadline, "no close-ok sent");
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert!(matches!(
+            frame,
+            AMQPFrame::Method(_, AMQPClass::Channel(channel::AMQPMethod::CloseOk(_)))
+        ));
+        let mut closed = Vec::new();
+        while closed.is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            closed = channel_closed_events(&mut events);
+        }
+        assert_eq!(
+            closed,
+            vec![Event::ChannelClosed {
+                channel_id: channel.id(),
+                reply_code: 404,
+                reply_text: "NOT_FOUND".into(),
+            }]
+        );
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(channel_closed_events(&mut events).is_empty());
+    }
+
+    /// Drive `fut`, answering the methods it sends like the server would, except for
+    /// basic.cancel when `answer_cancel` is false. Returns its output along with the names of
+    /// these methods.
+    fn run_with_server<T>(
+        conn: &Connection,
+        frames: &Frames,
+        answer_cancel: bool,
+        fut: impl std::future::Future<Output = T>,
+    ) -> (T, Vec<&'static str>) {
+        use amq_protocol::protocol::{channel, connection};
+        use std::time::{Duration, Instant};
+
+        let mut fut = Box::pin(fut);
+        let mut sent = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(output) = future::block_on(future::poll_once(&mut fut)) {
+                return (output, sent);
+            }
+            while let Some((frame, resolver)) = frames.pop(true) {
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                let (channel_id, class) = match frame {
+                    AMQPFrame::Method(channel_id, class) => (channel_id, class),
+                    // The content of a publish
+                    _ => continue,
+                };
+                let (name, reply) = match class {
+                    AMQPClass::Basic(basic::AMQPMethod::Publish(_)) => ("basic.publish", None),
+                    AMQPClass::Basic(basic::AMQPMethod::Cancel(cancel)) => (
+                        "basic.cancel",
+                        Some(AMQPClass::Basic(basic::AMQPMethod::CancelOk(
+                            basic::CancelOk {
+                                consumer_tag: cancel.consumer_tag,
+                            },
+                        )))
+                        .filter(|_| answer_cancel),
+                    ),
+                    AMQPClass::Channel(channel::AMQPMethod::Close(_)) => (
+                        "channel.close",
+                        Some(AMQPClass::Channel(channel::AMQPMethod::CloseOk(
+                            channel::CloseOk {},
+                        ))),
+                    ),
+                    AMQPClass::Connection(connection::AMQPMethod::Close(_)) => (
+                        "connection.close",
+                        Some(AMQPClass::Connection(connection::AMQPMethod::CloseOk(
+                            connection::CloseOk {},
+                        ))),
+                    ),
+                    class => panic!("unexpected method: {:?}", class),
+                };
+                sent.push(name);
+                if let Some(reply) = reply {
+                    conn.channels
+                        .handle_frame(AMQPFrame::Method(channel_id, reply))
+                        .unwrap();
+                }
+            }
+            assert!(Instant::now() < deadline, "future didn't complete");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// A channel in confirm mode with a consumer, an unacked delivery and an unconfirmed publish
+    fn busy_channel() -> (Connection, Channel, Frames) {
+        use crate::consumer::Consumer;
+
+        let (conn, channel, frames) = connected_channel();
+        conn.configuration.set_frame_max(4096);
+        let consumer_tag = ShortString::from("consumer-tag");
+        channel.register_consumer(
+            consumer_tag.clone(),
+            Consumer::new(
+                consumer_tag,
+                Arc::new(async_global_executor_trait::AsyncGlobalExecutor),
+                None,
+                "queue".into(),
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            ),
+        );
+        channel.status().register_delivery(1);
+        channel.status().set_confirm();
+        let (res, sent) = run_with_server(
+            &conn,
+            &frames,
+            true,
+            channel.basic_publish(
+                "",
+                "queue",
+                Default::default(),
+                b"payload".to_vec(),
+                BasicProperties::default(),
+            ),
+        );
+        res.unwrap();
+        assert_eq!(sent, vec!["basic.publish"]);
+        (conn, channel, frames)
+    }
+
+    #[test]
+    fn shutdown_drains_before_closing() {
+        use std::time::{Duration, Instant};
+
+        let (conn, channel, frames) = busy_channel();
+        let start = Instant::now();
+        let settle = {
+            let channels = conn.channels.clone();
+            let channel = channel.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(100));
+                // The application acks its delivery and the server confirms the publish
+                channel.status().complete_delivery(1, false);
+                channels
+                    .handle_frame(AMQPFrame::Method(
+                        channel.id(),
+                        AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                            delivery_tag: 1,
+                            multiple: false,
+                        })),
+                    ))
+                    .unwrap();
+            })
+        };
+
+        let (report, sent) =
+            run_with_server(&conn, &frames, true, conn.shutdown(Duration::from_secs(5)));
+        settle.join().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(report, Ok(ShutdownReport::default()));
+        assert!(report.unwrap().is_complete());
+        assert_eq!(
+            sent,
+            vec!["basic.cancel", "channel.close", "connection.close"]
+        );
+    }
+
+    #[test]
+    fn drained_wakes_up() {
+        use std::time::{Duration, Instant};
+
+        let (_conn, channel, _frames) = connected_channel();
+        channel.status().register_delivery(1);
+        let start = Instant::now();
+        let settle = {
+            let channel = channel.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                channel.status().complete_delivery(1, false);
+            })
+        };
+        future::block_on(channel.wait_drained(start + Duration::from_secs(5)));
+        settle.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(channel.unacked_deliveries().is_empty());
+    }
+
+    #[test]
+    fn shutdown_report_after_deadline() {
+        use std::time::Duration;
+
+        let (conn, channel, frames) = busy_channel();
+
+        // The server never answers the cancel, nothing gets acked nor confirmed
+        let (report, sent) = run_with_server(
+            &conn,
+            &frames,
+            false,
+            conn.shutdown(Duration::from_millis(100)),
+        );
+        let report = report.unwrap();
+        assert!(!report.is_complete());
+        assert_eq!(
+            report,
+            ShutdownReport {
+                uncancelled_consumers: vec![(channel.id(), "consumer-tag".into())],
+                unacked_deliveries: vec![(channel.id(), 1)],
+                unconfirmed_publishes: vec![(channel.id(), 1)],
+            }
+        );
+        // The channel and the connection get closed anyway
+        assert_eq!(
+            sent,
+            vec!["basic.cancel", "channel.close", "connection.close"]
+        );
+    }
+
+    /// How the server answers a publish
+    #[derive(Clone, Copy, Debug)]
+    enum Answer {
+        Ack,
+        Nack,
+        /// The confirmation gets lost because of a channel error
+        ChannelError,
+    }
+
+    /// Drive `fut`, answering each publish it sends with the next of `answers`. Counts the
+    /// publishes in `published`.
+    fn serve_confirms<T>(
+        conn: &Connection,
+        channel: &Channel,
+        frames: &Frames,
+        answers: &mut std::collections::VecDeque<Answer>,
+        published: &mut DeliveryTag,
+        fut: impl std::future::Future<Output = T>,
+    ) -> T {
+        use std::time::{Duration, Instant};
+
+        let mut fut = Box::pin(fut);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(output) = future::block_on(future::poll_once(&mut fut)) {
+                return output;
+            }
+            while let Some((frame, resolver)) = frames.pop(true) {
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                match frame {
+                    AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(_))) => {}
+                    // The content of a publish
+                    AMQPFrame::Header(..) | AMQPFrame::Body(..) => continue,
+                    frame => panic!("unexpected frame: {:?}", frame),
+                }
+                *published += 1;
+                let delivery_tag = *published;
+                let method = match answers.pop_front().expect("unexpected publish") {
+                    Answer::Ack => basic::AMQPMethod::Ack(basic::Ack {
+                        delivery_tag,
+                        multiple: false,
+                    }),
+                    Answer::Nack => basic::AMQPMethod::Nack(basic::Nack {
+                        delivery_tag,
+                        multiple: false,
+                        requeue: false,
+                    }),
+                    Answer::ChannelError => {
+                        channel.error_publisher_confirms(Error::InvalidChannelState(
+                            ChannelState::Error,
+                        ));
+                        continue;
+                    }
+                };
+                conn.channels
+                    .handle_frame(AMQPFrame::Method(channel.id(), AMQPClass::Basic(method)))
+                    .unwrap();
+            }
+            assert!(Instant::now() < deadline, "future didn't complete");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Publish a message through a ReliablePublisher, the server answering each attempt with the
+    /// next of `answers`. Returns the outcome and how many times the message got published.
+    fn reliable_publish(
+        max_retries: usize,
+        answers: &[Answer],
+    ) -> (Result<crate::PublishOutcome>, DeliveryTag) {
+        use crate::{ReliablePublisher, RetryPolicy};
+
+        let (conn, channel, frames) = connected_channel();
+        conn.configuration.set_frame_max(4096);
+        channel.status().set_confirm();
+        let mut answers = answers.iter().copied().collect();
+        let mut published = 0;
+        let publisher = future::block_on(ReliablePublisher::new(
+            &channel,
+            1,
+            RetryPolicy {
+                max_retries,
+                initial_delay: Duration::from_millis(1),
+                ..RetryPolicy::default()
+            },
+        ))
+        .unwrap();
+        let handle = serve_confirms(
+            &conn,
+            &channel,
+            &frames,
+            &mut answers,
+            &mut published,
+            publisher.publish(
+                "",
+                "queue",
+                Default::default(),
+                b"payload".to_vec(),
+                BasicProperties::default(),
+            ),
+        )
+        .unwrap();
+        let outcome = serve_confirms(
+            &conn,
+            &channel,
+            &frames,
+            &mut answers,
+            &mut published,
+            handle,
+        );
+        assert!(answers.is_empty(), "unused answers: {:?}", answers);
+        assert_eq!(publisher.in_flight(), 0);
+        (outcome, published)
+    }
+
+    #[test]
+    fn reliable_publish_acked() {
+        let (outcome, published) = reliable_publish(3, &[Answer::Ack]);
+        assert_eq!(
+            outcome,
+            Ok(crate::PublishOutcome {
+                confirmation: Confirmation::Ack(None),
+                attempts: 1,
+            })
+        );
+        assert_eq!(published, 1);
+    }
+
+    #[test]
+    fn reliable_publish_republishes_nacked() {
+        let (outcome, published) = reliable_publish(3, &[Answer::Nack, Answer::Nack, Answer::Ack]);
+        assert_eq!(
+            outcome,
+            Ok(crate::PublishOutcome {
+                confirmation: Confirmation::Ack(None),
+                attempts: 3,
+            })
+        );
+        assert_eq!(published, 3);
+    }
+
+    #[test]
+    fn reliable_publish_republishes_after_channel_error() {
+        let (outcome, published) = reliable_publish(3, &[Answer::ChannelError, Answer::Ack]);
+        assert_eq!(
+            outcome,
+            Ok(crate::PublishOutcome {
+                confirmation: Confirmation::Ack(None),
+                attempts: 2,
+            })
+        );
+        assert_eq!(published, 2);
+    }
+
+    #[test]
+    fn reliable_publish_gives_up() {
+        // Still nacked after the last retry
+        let (outcome, published) = reliable_publish(1, &[Answer::Nack, Answer::Nack]);
+        assert_eq!(
+            outcome,
+            Ok(crate::PublishOutcome {
+                confirmation: Confirmation::Nack(None),
+                attempts: 2,
+            })
+        );
+        assert_eq!(published, 2);
+
+        // The last attempt got dropped by a channel error
+        let (outcome, published) = reliable_publish(1, &[Answer::Nack, Answer::ChannelError]);
+        assert_eq!(
+            outcome,
+            Err(Error::InvalidChannelState(ChannelState::Error))
+        );
+        assert_eq!(published, 2);
+    }
+
+    #[test]
+    fn publish_batch_keeps_each_outcome() {
+        use crate::message::BatchMessage;
+
+        let (conn, channel, frames) = connected_channel();
+        conn.configuration.set_frame_max(4096);
+        channel.status().set_confirm();
+        let mut answers = vec![Answer::Ack, Answer::Nack, Answer::ChannelError]
+            .into_iter()
+            .collect();
+        let mut published = 0;
+        let messages = (0..3)
+            .map(|_| BatchMessage::new("", "queue", b"payload".to_vec()))
+            .collect::<Vec<_>>();
+        let confirm = serve_confirms(
+            &conn,
+            &channel,
+            &frames,
+            &mut answers,
+            &mut published,
+            channel.basic_publish_batch(messages),
+        )
+        .unwrap();
+        let confirmation = serve_confirms(
+            &conn,
+            &channel,
+            &frames,
+            &mut answers,
+            &mut published,
+            confirm,
+        );
+        assert!(answers.is_empty(), "unused answers: {:?}", answers);
+        assert_eq!(published, 3);
+        assert_eq!(
+            confirmation.confirmations,
+            vec![
+                Ok(Confirmation::Ack(None)),
+                Ok(Confirmation::Nack(None)),
+                Err(Error::InvalidChannelState(ChannelState::Error)),
+            ]
+        );
+        assert_eq!(confirmation.acks(), 1);
+        assert_eq!(confirmation.nacks(), 1);
+        assert_eq!(confirmation.errors(), 1);
+        assert!(!confirmation.all_acked());
+    }
+
+    /// Have the server block the connection with `reason`, or unblock it
+    fn set_blocked(conn: &Connection, reason: Option<&str>) {
+        use amq_protocol::protocol::connection;
+
+        let method = match reason {
+            Some(reason) => connection::AMQPMethod::Blocked(connection::Blocked {
+                reason: reason.into(),
+            }),
+            None => connection::AMQPMethod::Unblocked(connection::Unblocked {}),
+        };
+        conn.channels
+            .handle_frame(AMQPFrame::Method(0, AMQPClass::Connection(method)))
+            .unwrap();
+    }
+
+    /// Have the server pause or resume the flow of `channel`, waiting for the flow-ok
+    fn set_flow(conn: &Connection, channel: &Channel, frames: &Frames, active: bool) {
+        use amq_protocol::protocol::channel;
+        use std::time::{Duration, Instant};
+
+        conn.channels
+            .handle_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Channel(channel::AMQPMethod::Flow(channel::Flow { active })),
+            ))
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some((frame, resolver)) = frames.pop(true) {
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                match frame {
+                    AMQPFrame::Method(_, AMQPClass::Channel(channel::AMQPMethod::FlowOk(ok))) => {
+                        assert_eq!(ok.active, active);
+                        return;
+                    }
+                    frame => panic!("unexpected frame: {:?}", frame),
+                }
+            }
+            assert!(Instant::now() < deadline, "no flow-ok sent");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn publish(channel: &Channel) -> impl std::future::Future<Output = Result<PublisherConfirm>> {
+        let channel = channel.clone();
+        async move {
+            channel
+                .basic_publish(
+                    "",
+                    "queue",
+                    Default::default(),
+                    b"payload".to_vec(),
+                    BasicProperties::default(),
+                )
+                .await
+        }
+    }
+
+    #[test]
+    fn publish_waits_until_unblocked() {
+        let (conn, channel, frames) = connected_channel();
+        conn.configuration.set_frame_max(4096);
+        set_blocked(&conn, Some("low on memory"));
+        assert_eq!(
+            conn.status.blocked_reason(),
+            Some("low on memory".to_string())
+        );
+
+        let mut unblocked = conn.until_unblocked();
+        assert!(future::block_on(future::poll_once(&mut unblocked)).is_none());
+        let mut publish = Box::pin(publish(&channel));
+        assert!(future::block_on(future::poll_once(&mut publish)).is_none());
+        assert!(frames.pop(true).is_none());
+
+        set_blocked(&conn, None);
+        assert!(future::block_on(future::poll_once(&mut unblocked)).is_some());
+        let (res, sent) = run_with_server(&conn, &frames, true, publish);
+        res.unwrap();
+        assert_eq!(sent, vec!["basic.publish"]);
+    }
+
+    #[test]
+    fn publish_waits_until_flow_resumed() {
+        let (conn, channel, frames) = connected_channel();
+        conn.configuration.set_frame_max(4096);
+        set_flow(&conn, &channel, &frames, false);
+
+        let mut flow_resumed = channel.status().until_flow();
+        assert!(future::block_on(future::poll_once(&mut flow_resumed)).is_none());
+        let mut publish = Box::pin(publish(&channel));
+        assert!(future::block_on(future::poll_once(&mut publish)).is_none());
+        assert!(frames.pop(true).is_none());
+
+        set_flow(&conn, &channel, &frames, true);
+        assert!(future::block_on(future::poll_once(&mut flow_resumed)).is_some());
+        let (res, sent) = run_with_server(&conn, &frames, true, publish);
+        res.unwrap();
+        assert_eq!(sent, vec!["basic.publish"]);
+    }
+
+    #[test]
+    fn publish_fails_fast() {
+        let (conn, channel, frames) = connected_channel();
+        conn.configuration.set_frame_max(4096);
+        conn.configuration
+            .set_publish_backpressure(crate::PublishBackpressure::FailFast);
+
+        set_flow(&conn, &channel, &frames, false);
+        assert_eq!(
+            future::block_on(publish(&channel)).err(),
+            Some(Error::ChannelFlowPaused(channel.id()))
+        );
+
+        // The blocked connection takes precedence
+        set_blocked(&conn, Some("low on memory"));
+        assert_eq!(
+            future::block_on(publish(&channel)).err(),
+            Some(Error::ConnectionBlocked("low on memory".into()))
+        );
+
+        set_blocked(&conn, None);
+        assert_eq!(
+            future::block_on(publish(&channel)).err(),
+            Some(Error::ChannelFlowPaused(channel.id()))
+        );
+        assert!(frames.pop(true).is_none());
+
+        set_flow(&conn, &channel, &frames, true);
+        let (res, sent) = run_with_server(&conn, &frames, true, publish(&channel));
+        res.unwrap();
+        assert_eq!(sent, vec!["basic.publish"]);
+    }
+
+    /// What the client did while `serve_consumer` acted as the server
+    #[derive(Debug, Default)]
+    struct ConsumerLog {
+        /// The prefetch count set through basic.qos
+        prefetch_count: Option<crate::types::ShortUInt>,
+        /// The methods sent by the client, along with the confirms sent by the server
+        sent: Vec<String>,
+        /// The routing key and properties of each message published by the client
+        published: Vec<(ShortString, BasicProperties)>,
+    }
+
+    /// Drive `fut`, acting as the server for a consumer: answer basic.qos and basic.consume, then
+    /// deliver one message per item of `deliveries`, which tells whether it is redelivered along
+    /// with its properties. Publishes get confirmed with the next of `answers`, once the client
+    /// got idle. The consumer gets cancelled by the server once each delivery got settled.
+    fn serve_consumer<T>(
+        conn: &Connection,
+        channel: &Channel,
+        frames: &Frames,
+        deliveries: Vec<(bool, BasicProperties)>,
+        answers: &mut std::collections::VecDeque<Answer>,
+        fut: impl std::future::Future<Output = T>,
+    ) -> (T, ConsumerLog) {
+        use std::time::{Duration, Instant};
+
+        let handle = |method| {
+            conn.channels
+                .handle_frame(AMQPFrame::Method(channel.id(), AMQPClass::Basic(method)))
+                .unwrap();
+        };
+        let mut fut = Box::pin(fut);
+        let mut log = ConsumerLog::default();
+        let mut consumer_tag = None;
+        let mut routing_key = None;
+        let mut unconfirmed = Vec::new();
+        let mut published = 0;
+        let mut settled = 0;
+        let mut idle = 0;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(output) = future::block_on(future::poll_once(&mut fut)) {
+                return (output, log);
+            }
+            idle += 1;
+            while let Some((frame, resolver)) = frames.pop(true) {
+                idle = 0;
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                let method = match frame {
+                    AMQPFrame::Method(_, AMQPClass::Basic(method)) => method,
+                    AMQPFrame::Header(_, _, header) => {
+                        let routing_key = routing_key.take().expect("unexpected content");
+                        log.published.push((routing_key, header.properties));
+                        continue;
+                    }
+                    AMQPFrame::Body(..) => continue,
+                    frame => panic!("unexpected frame: {:?}", frame),
+                };
+                match method {
+                    basic::AMQPMethod::Qos(qos) => {
+                        log.sent.push("basic.qos".into());
+                        log.prefetch_count = Some(qos.prefetch_count);
+                        handle(basic::AMQPMethod::QosOk(basic::QosOk {}));
+                    }
+                    basic::AMQPMethod::Consume(consume) => {
+                        log.sent.push("basic.consume".into());
+                        handle(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                            consumer_tag: consume.consumer_tag.clone(),
+                        }));
+                        for (delivery_tag, (redelivered, properties)) in
+                            (1..).zip(deliveries.iter().cloned())
+                        {
+                            handle(basic::AMQPMethod::Deliver(basic::Deliver {
+                                consumer_tag: consume.consumer_tag.clone(),
+                                delivery_tag,
+                                redelivered,
+                                exchange: "".into(),
+                                routing_key: consume.queue.clone(),
+                            }));
+                            conn.channels
+                                .handle_frame(AMQPFrame::Header(
+                                    channel.id(),
+                                    60,
+                                    Box::new(AMQPContentHeader {
+                                        class_id: 60,
+                                        body_size: 4,
+                                        properties,
+                                    }),
+                                ))
+                                .unwrap();
+                            conn.channels
+                                .handle_frame(AMQPFrame::Body(channel.id(), b"ping".to_vec()))
+                                .unwrap();
+                        }
+                        consumer_tag = Some(consume.consumer_tag);
+                    }
+                    basic::AMQPMethod::Publish(publish) => {
+                        log.sent.push("basic.publish".into());
+                        routing_key = Some(publish.routing_key);
+                        published += 1;
+                        unconfirmed.push(published);
+                    }
+                    basic::AMQPMethod::Ack(ack) => {
+                        log.sent.push(format!("basic.ack {}", ack.delivery_tag));
+                        settled += 1;
+                    }
+                    basic::AMQPMethod::Nack(nack) => {
+                        log.sent.push(if nack.requeue {
+                            format!("basic.nack {} requeue", nack.delivery_tag)
+                        } else {
+                            format!("basic.nack {}", nack.delivery_tag)
+                        });
+                        settled += 1;
+                    }
+                    method => panic!("unexpected method: {:?}", method),
+                }
+            }
+            // Only confirm once the client got the time to (wrongly) settle the request first
+            if idle > 5 {
+                for delivery_tag in unconfirmed.drain(..) {
+                    match answers.pop_front().expect("unexpected publish") {
+                        Answer::Ack => {
+                            log.sent.push("confirm.ack".into());
+                            handle(basic::AMQPMethod::Ack(basic::Ack {
+                                delivery_tag,
+                                multiple: false,
+                            }));
+                        }
+                        Answer::Nack => {
+                            log.sent.push("confirm.nack".into());
+                            handle(basic::AMQPMethod::Nack(basic::Nack {
+                                delivery_tag,
+                                multiple: false,
+                                requeue: false,
+                            }));
+                        }
+                        Answer::ChannelError => {
+                            log.sent.push("confirm.error".into());
+                            channel.error_publisher_confirms(Error::InvalidChannelState(
+                                ChannelState::Error,
+                            ));
+                        }
+                    }
+                }
+            }
+            if settled == deliveries.len() && idle > 5 {
+                if let Some(consumer_tag) = consumer_tag.take() {
+                    handle(basic::AMQPMethod::Cancel(basic::Cancel {
+                        consumer_tag,
+                        nowait: true,
+                    }));
+                }
+            }
+            assert!(
+                Instant::now() < deadline,
+                "future didn't complete: {:?}",
+                log
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Run an RpcServer replying "pong" to the requests with the given properties
+    fn rpc_server(
+        requests: Vec<BasicProperties>,
+        answers: &[Answer],
+    ) -> (Result<()>, ConsumerLog, usize) {
+        use crate::RpcServer;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let (conn, channel, frames) = consuming_channel();
+        conn.configuration.set_frame_max(4096);
+        channel.status().set_confirm();
+        let mut answers = answers.iter().copied().collect();
+        let handled = Arc::new(AtomicUsize::new(0));
+        let counter = handled.clone();
+        let (res, log) = serve_consumer(
+            &conn,
+            &channel,
+            &frames,
+            requests.into_iter().map(|p| (false, p)).collect(),
+            &mut answers,
+            RpcServer::new(&channel, 4).serve(
+                "requests",
+                "ctag",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+                move |delivery| {
+                    assert_eq!(delivery.data.as_ref(), b"ping");
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    async { Ok::<_, String>(b"pong".to_vec()) }
+                },
+            ),
+        );
+        assert!(answers.is_empty(), "unused answers: {:?}", answers);
+        (res, log, handled.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn rpc_server_acks_after_reply_confirmed() {
+        let request = BasicProperties::default()
+            .with_reply_to("reply-queue".into())
+            .with_correlation_id("request-1".into());
+        let (res, log, handled) = rpc_server(vec![request], &[Answer::Ack]);
+        assert_eq!(res, Err(Error::ConsumerCancelledByServer("ctag".into())));
+        assert_eq!(handled, 1);
+        assert_eq!(log.prefetch_count, Some(4));
+        assert_eq!(
+            log.sent,
+            vec![
+                "basic.qos",
+                "basic.consume",
+                "basic.publish",
+                "confirm.ack",
+                "basic.ack 1"
+            ]
+        );
+        assert_eq!(
+            log.published,
+            vec![(
+                "reply-queue".into(),
+                BasicProperties::default().with_correlation_id("request-1".into())
+            )]
+        );
+    }
+
+    #[test]
+    fn rpc_server_dead_letters_without_reply_to() {
+        let request = BasicProperties::default().with_correlation_id("request-1".into());
+        let (res, log, handled) = rpc_server(vec![request], &[]);
+        assert_eq!(res, Err(Error::ConsumerCancelledByServer("ctag".into())));
+        assert_eq!(handled, 0);
+        assert_eq!(log.sent, vec!["basic.qos", "basic.consume", "basic.nack 1"]);
+        assert!(log.published.is_empty());
+    }
+
+    #[test]
+    fn rpc_server_requeues_nacked_reply() {
+        let request = BasicProperties::default()
+            .with_reply_to("reply-queue".into())
+            .with_correlation_id("request-1".into());
+        let (res, log, handled) = rpc_server(vec![request], &[Answer::Nack]);
+        assert_eq!(res, Err(Error::ConsumerCancelledByServer("ctag".into())));
+        assert_eq!(handled, 1);
+        assert_eq!(
+            log.sent,
+            vec![
+                "basic.qos",
+                "basic.consume",
+                "basic.publish",
+                "confirm.nack",
+                "basic.nack 1 requeue"
+            ]
+        );
+    }
+
+    /// Run `runner` on deliveries with the given redelivered flags and correlation ids
+    fn run_consumer_runner<H, F>(
+        runner: impl FnOnce(&Channel) -> crate::ConsumerRunner,
+        deliveries: &[(bool, &str)],
+        handler: H,
+    ) -> ConsumerLog
+    where
+        H: Fn(crate::message::Delivery) -> F + Send + Sync + 'static,
+        F: std::future::Future<Output = std::result::Result<crate::Outcome, String>>
+            + Send
+            + 'static,
+    {
+        let (conn, channel, frames) = consuming_channel();
+        conn.configuration.set_frame_max(4096);
+        let deliveries = deliveries
+            .iter()
+            .map(|(redelivered, correlation_id)| {
+                let properties =
+                    BasicProperties::default().with_correlation_id((*correlation_id).into());
+                (*redelivered, properties)
+            })
+            .collect();
+        let runner = runner(&channel);
+        let (res, log) = serve_consumer(
+            &conn,
+            &channel,
+            &frames,
+            deliveries,
+            &mut Default::default(),
+            runner.run(
+                "queue",
+                "ctag",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+                handler,
+            ),
+        );
+        assert_eq!(res, Err(Error::ConsumerCancelledByServer("ctag".into())));
+        assert!(log.published.is_empty());
+        log
+    }
+
+    /// Settle the delivery as its correlation id says
+    async fn settle_as_told(
+        delivery: crate::message::Delivery,
+    ) -> std::result::Result<crate::Outcome, String> {
+        use crate::Outcome;
+
+        match delivery
+            .properties
+            .correlation_id()
+            .as_ref()
+            .map(ShortString::as_str)
+        {
+            Some("ack") => Ok(Outcome::Ack),
+            Some("requeue") => Ok(Outcome::Requeue),
+            Some("dead-letter") => Ok(Outcome::DeadLetter),
+            Some("fail") => Err("failed".into()),
+            _ => panic!("handler panicked"),
+        }
+    }
+
+    #[test]
+    fn consumer_runner_settles_outcomes() {
+        let log = run_consumer_runner(
+            |channel| crate::ConsumerRunner::new(channel, 1),
+            &[(false, "ack"), (false, "requeue"), (false, "dead-letter")],
+            settle_as_told,
+        );
+        assert_eq!(log.prefetch_count, Some(1));
+        assert_eq!(
+            log.sent,
+            vec![
+                "basic.qos",
+                "basic.consume",
+                "basic.ack 1",
+                "basic.nack 2 requeue",
+                "basic.nack 3"
+            ]
+        );
+    }
+
+    #[test]
+    fn consumer_runner_failure_policy() {
+        use crate::FailurePolicy;
+
+        let deliveries = [
+            (false, "fail"),
+            (true, "fail"),
+            (false, "panic"),
+            (true, "panic"),
+        ];
+        // RequeueOnce by default, for failures and panics alike
+        let log = run_consumer_runner(
+            |channel| crate::ConsumerRunner::new(channel, 1),
+            &deliveries,
+            settle_as_told,
+        );
+        assert_eq!(
+            log.sent[2..],
+            [
+                "basic.nack 1 requeue",
+                "basic.nack 2",
+                "basic.nack 3 requeue",
+                "basic.nack 4"
+            ]
+        );
+
+        let log = run_consumer_runner(
+            |channel| {
+                crate::ConsumerRunner::new(channel, 1).with_failure_policy(FailurePolicy::Requeue)
+            },
+            &deliveries,
+            settle_as_told,
+        );
+        assert_eq!(
+            log.sent[2..],
+            [
+                "basic.nack 1 requeue",
+                "basic.nack 2 requeue",
+                "basic.nack 3 requeue",
+                "basic.nack 4 requeue"
+            ]
+        );
+
+        let log = run_consumer_runner(
+            |channel| {
+                crate::ConsumerRunner::new(channel, 1)
+                    .with_failure_policy(FailurePolicy::DeadLetter)
+            },
+            &deliveries,
+            settle_as_told,
+        );
+        assert_eq!(
+            log.sent[2..],
+            [
+                "basic.nack 1",
+                "basic.nack 2",
+                "basic.nack 3",
+                "basic.nack 4"
+            ]
+        );
+    }
+
+    #[test]
+    fn consumer_runner_bounds_in_flight() {
+        use crate::Outcome;
+        use std::{
+            sync::atomic::{AtomicUsize, Ordering},
+            time::{Duration, Instant},
+        };
+
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let (running_, max_running_) = (running.clone(), max_running.clone());
+        let log = run_consumer_runner(
+            |channel| crate::ConsumerRunner::new(channel, 2),
+            &[(false, "ack"); 5],
+            move |_| {
+                let running = running_.clone();
+                let max_running = max_running_.clone();
+                async move {
+                    let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now_running, Ordering::SeqCst);
+                    // Hold the delivery long enough for the other ones to get handled too, were
+                    // they not bounded
+                    let deadline = Instant::now() + Duration::from_millis(20);
+                    while Instant::now() < deadline {
+                        future::yield_now().await;
+                    }
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Ok(Outcome::Ack)
+                }
+            },
+        );
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+        assert_eq!(running.load(Ordering::SeqCst), 0);
+        assert_eq!(log.prefetch_count, Some(2));
+        let mut acks = log.sent[2..].to_vec();
+        acks.sort();
+        assert_eq!(
+            acks,
+            vec![
+                "basic.ack 1",
+                "basic.ack 2",
+                "basic.ack 3",
+                "basic.ack 4",
+                "basic.ack 5"
+            ]
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn deprecated_locale_is_preferred() {
+        let options = ConnectionProperties::default();
+        assert_eq!(options.preferred_locales(), vec!["en_US".to_string()]);
+
+        let options = ConnectionProperties {
+            locale: "fr_FR".into(),
+            ..ConnectionProperties::default()
+        };
+        assert_eq!(
+            options.preferred_locales(),
+            vec!["fr_FR".to_string(), "en_US".to_string()]
+        );
+
+        let options = ConnectionProperties {
+            locale: "en_US".into(),
+            ..ConnectionProperties::default()
+        }
+        .with_locales(vec!["de_DE".into(), "en_US".into()]);
+        assert_eq!(
+            options.preferred_locales(),
+            vec!["en_US".to_string(), "de_DE".to_string()]
+        );
+    }
 }
@@ -1,23 +1,54 @@
-use crate::types::{AMQPValue, FieldTable, LongString};
+use crate::{
+    types::{AMQPValue, FieldTable, LongString},
+    BufferLimits, BufferOverflowPolicy, CredentialsProvider, EndpointSelection,
+    PublishBackpressure, RecoveryConfig, SaslMechanism,
+};
 use executor_trait::FullExecutor;
 use reactor_trait::Reactor;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 #[derive(Clone)]
 pub struct ConnectionProperties {
+    /// Preferred over `locales` when not empty, as the only locale we used to request
+    #[deprecated(note = "use locales or with_locales instead")]
     pub locale: String,
+    pub locales: Vec<String>,
     pub client_properties: FieldTable,
     pub executor: Option<Arc<dyn FullExecutor + Send + Sync>>,
     pub reactor: Option<Arc<dyn Reactor + Send + Sync>>,
+    pub recovery_config: Option<RecoveryConfig>,
+    pub endpoint_selection: EndpointSelection,
+    pub connection_timeout: Option<Duration>,
+    pub tls_handshake_timeout: Option<Duration>,
+    pub amqp_handshake_timeout: Option<Duration>,
+    pub sasl_mechanisms: Vec<Arc<dyn SaslMechanism>>,
+    pub credentials_provider: Option<Arc<dyn CredentialsProvider>>,
+    pub publish_backpressure: PublishBackpressure,
+    pub consumer_buffer_limits: BufferLimits,
+    pub connection_buffer_limits: BufferLimits,
+    pub buffer_overflow_policy: BufferOverflowPolicy,
 }
 
 impl Default for ConnectionProperties {
     fn default() -> Self {
+        #[allow(deprecated)]
         Self {
-            locale: "en_US".into(),
+            locale: String::new(),
+            locales: vec!["en_US".into()],
             client_properties: FieldTable::default(),
             executor: None,
             reactor: None,
+            recovery_config: None,
+            endpoint_selection: EndpointSelection::default(),
+            connection_timeout: None,
+            tls_handshake_timeout: None,
+            amqp_handshake_timeout: None,
+            sasl_mechanisms: Vec::new(),
+            credentials_provider: None,
+            publish_backpressure: PublishBackpressure::default(),
+            consumer_buffer_limits: BufferLimits::default(),
+            connection_buffer_limits: BufferLimits::default(),
+            buffer_overflow_policy: BufferOverflowPolicy::default(),
         }
     }
 }
@@ -40,4 +71,121 @@ impl ConnectionProperties {
         self.reactor = Some(Arc::new(reactor));
         self
     }
+
+    /// Enable automatic connection recovery, see [`RecoveryConfig`].
+    ///
+    /// [`RecoveryConfig`]: ./struct.RecoveryConfig.html
+    pub fn with_recovery_config(mut self, config: RecoveryConfig) -> Self {
+        self.recovery_config = Some(config);
+        self
+    }
+
+    /// How to pick the endpoint to use when connecting to one of several endpoints
+    pub fn with_endpoint_selection(mut self, selection: EndpointSelection) -> Self {
+        self.endpoint_selection = selection;
+        self
+    }
+
+    /// Timeout for each TCP connection attempt, unless the URI specifies its own
+    /// `connection_timeout`
+    pub fn with_connection_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for the TLS handshake when connecting to an `amqps` URI
+    pub fn with_tls_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.tls_handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for the AMQP handshake, from the protocol header up to connection.open-ok
+    ///
+    /// This also applies to the handshakes performed while recovering the connection.
+    pub fn with_amqp_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.amqp_handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Add a SASL mechanism to the ones we can authenticate with, see [`SaslMechanism`].
+    ///
+    /// The mechanisms are tried in the order they were added, the first one offered by the
+    /// server gets used. If none is set, we use the `auth_mechanism` from the URI.
+    ///
+    /// [`SaslMechanism`]: ./trait.SaslMechanism.html
+    pub fn with_sasl_mechanism<M: SaslMechanism + 'static>(mut self, mechanism: M) -> Self {
+        self.sasl_mechanisms.push(Arc::new(mechanism));
+        self
+    }
+
+    /// The locales we accept, by order of preference. The first one offered by the server gets
+    /// used.
+    pub fn with_locales(mut self, locales: Vec<String>) -> Self {
+        self.locales = locales;
+        self
+    }
+
+    /// The locales we accept, by order of preference, including the deprecated `locale`
+    pub(crate) fn preferred_locales(&self) -> Vec<String> {
+        #[allow(deprecated)]
+        let mut locales = if self.locale.is_empty() {
+            Vec::new()
+        } else {
+            vec![self.locale.clone()]
+        };
+        for locale in &self.locales {
+            if !locales.contains(locale) {
+                locales.push(locale.clone());
+            }
+        }
+        locales
+    }
+
+    /// Fetch the credentials from `provider` instead of using the ones from the URI, see
+    /// [`CredentialsProvider`].
+    ///
+    /// [`CredentialsProvider`]: ./trait.CredentialsProvider.html
+    pub fn with_credentials_provider<P: CredentialsProvider + 'static>(
+        mut self,
+        provider: P,
+    ) -> Self {
+        self.credentials_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// Whether publishing waits or fails while the server blocked the connection or paused the
+    /// channel flow, see [`PublishBackpressure`].
+    ///
+    /// [`PublishBackpressure`]: ./enum.PublishBackpressure.html
+    pub fn with_publish_backpressure(mut self, publish_backpressure: PublishBackpressure) -> Self {
+        self.publish_backpressure = publish_backpressure;
+        self
+    }
+
+    /// How many deliveries each consumer can buffer before they get consumed, see
+    /// [`BufferLimits`].
+    ///
+    /// [`BufferLimits`]: ./struct.BufferLimits.html
+    pub fn with_consumer_buffer_limits(mut self, limits: BufferLimits) -> Self {
+        self.consumer_buffer_limits = limits;
+        self
+    }
+
+    /// How many deliveries all the consumers of the connection can buffer together before they
+    /// get consumed, see [`BufferLimits`].
+    ///
+    /// [`BufferLimits`]: ./struct.BufferLimits.html
+    pub fn with_connection_buffer_limits(mut self, limits: BufferLimits) -> Self {
+        self.connection_buffer_limits = limits;
+        self
+    }
+
+    /// What happens once the consumers buffered too many deliveries, see
+    /// [`BufferOverflowPolicy`].
+    ///
+    /// [`BufferOverflowPolicy`]: ./enum.BufferOverflowPolicy.html
+    pub fn with_buffer_overflow_policy(mut self, policy: BufferOverflowPolicy) -> Self {
+        self.buffer_overflow_policy = policy;
+        self
+    }
 }
@@ -1,9 +1,19 @@
 use crate::{
-    auth::{Credentials, SASLMechanism},
-    Connection, ConnectionProperties, PromiseResolver,
+    auth::Credentials,
+    events::{Event, EventStream, Events},
+    sasl::{SaslMechanism, SaslSession},
+    uri::AMQPUri,
+    wakers::Wakers,
+    Connection, ConnectionProperties, PromiseResolver, ServerProperties,
 };
 use parking_lot::Mutex;
-use std::{fmt, sync::Arc};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
 
 #[derive(Clone, Default)]
 pub struct ConnectionStatus(Arc<Mutex<Inner>>);
@@ -47,16 +57,53 @@ impl ConnectionStatus {
         self.0.lock().username = username.into();
     }
 
-    pub(crate) fn block(&self) {
-        self.0.lock().blocked = true;
+    /// The endpoint we're currently connected (or connecting) to
+    pub fn endpoint(&self) -> Option<AMQPUri> {
+        self.0.lock().endpoint.clone()
+    }
+
+    pub(crate) fn set_endpoint(&self, endpoint: &AMQPUri) {
+        self.0.lock().endpoint = Some(endpoint.clone());
+    }
+
+    /// The properties sent by the server we're connected to
+    pub fn server_properties(&self) -> ServerProperties {
+        self.0.lock().server_properties.clone()
+    }
+
+    pub(crate) fn subscribe(&self) -> EventStream {
+        self.0.lock().events.subscribe()
+    }
+
+    pub(crate) fn emit(&self, event: Event) {
+        self.0.lock().events.emit(event);
+    }
+
+    pub(crate) fn set_server_properties(&self, server_properties: ServerProperties) {
+        self.0.lock().server_properties = server_properties;
+    }
+
+    pub(crate) fn block(&self, reason: &str) {
+        self.0.lock().blocked = Some(reason.into());
     }
 
     pub(crate) fn unblock(&self) {
-        self.0.lock().blocked = false;
+        let mut inner = self.0.lock();
+        inner.blocked = None;
+        inner.unblocked_wakers.wake();
     }
 
     pub fn blocked(&self) -> bool {
-        self.0.lock().blocked
+        self.0.lock().blocked.is_some()
+    }
+
+    /// Why the server blocked the connection, if it did
+    pub fn blocked_reason(&self) -> Option<String> {
+        self.0.lock().blocked.clone()
+    }
+
+    pub(crate) fn until_unblocked(&self) -> Unblocked {
+        Unblocked(self.clone())
     }
 
     pub fn connected(&self) -> bool {
@@ -71,6 +118,10 @@ impl ConnectionStatus {
         self.0.lock().state == ConnectionState::Closed
     }
 
+    pub fn reconnecting(&self) -> bool {
+        self.0.lock().state == ConnectionState::Reconnecting
+    }
+
     pub fn errored(&self) -> bool {
         self.0.lock().state == ConnectionState::Error
     }
@@ -80,15 +131,20 @@ impl ConnectionStatus {
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum ConnectionStep {
     ProtocolHeader(
         PromiseResolver<Connection>,
         Connection,
         Credentials,
-        SASLMechanism,
+        Vec<Arc<dyn SaslMechanism>>,
         ConnectionProperties,
     ),
-    StartOk(PromiseResolver<Connection>, Connection, Credentials),
+    StartOk(
+        PromiseResolver<Connection>,
+        Connection,
+        Box<dyn SaslSession>,
+    ),
     Open(PromiseResolver<Connection>),
 }
 
@@ -97,6 +153,7 @@ pub enum ConnectionState {
     Initial,
     Connecting,
     Connected,
+    Reconnecting,
     Closing,
     Closed,
     Error,
@@ -116,7 +173,14 @@ impl fmt::Debug for ConnectionStatus {
                 .field("state", &inner.state)
                 .field("vhost", &inner.vhost)
                 .field("username", &inner.username)
-                .field("blocked", &inner.blocked);
+                .field(
+                    "endpoint",
+                    &inner
+                        .endpoint
+                        .as_ref()
+                        .map(|uri| format!("{}:{}", uri.authority.host, uri.authority.port)),
+                )
+                .field("blocked", &inner.blocked.is_some());
         }
         debug.finish()
     }
@@ -127,7 +191,11 @@ struct Inner {
     state: ConnectionState,
     vhost: String,
     username: String,
-    blocked: bool,
+    endpoint: Option<AMQPUri>,
+    server_properties: ServerProperties,
+    blocked: Option<String>,
+    unblocked_wakers: Wakers,
+    events: Events,
 }
 
 impl Default for Inner {
@@ -137,7 +205,33 @@ impl Default for Inner {
             state: ConnectionState::default(),
             vhost: "/".into(),
             username: "guest".into(),
-            blocked: false,
+            endpoint: None,
+            server_properties: ServerProperties::default(),
+            blocked: None,
+            unblocked_wakers: Wakers::default(),
+            events: Events::default(),
+        }
+    }
+}
+
+/// A Future resolving once the server unblocked the connection.
+///
+/// See [`Connection::until_unblocked`].
+///
+/// [`Connection::until_unblocked`]: ./struct.Connection.html#method.until_unblocked
+#[derive(Debug)]
+pub struct Unblocked(ConnectionStatus);
+
+impl Future for Unblocked {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = (self.0).0.lock();
+        if inner.blocked.is_none() {
+            Poll::Ready(())
+        } else {
+            inner.unblocked_wakers.register(cx.waker());
+            Poll::Pending
         }
     }
 }
@@ -1,16 +1,19 @@
 use crate::{
     channel_closer::ChannelCloser,
+    consumer_buffer::{BufferLimits, ConsumerBuffer},
     consumer_canceler::ConsumerCanceler,
     consumer_status::{ConsumerState, ConsumerStatus},
     error_holder::ErrorHolder,
     internal_rpc::InternalRPCHandle,
     message::{Delivery, DeliveryResult},
-    options::BasicConsumeOptions,
+    options::{BasicConsumeOptions, BasicRejectOptions},
+    streaming::{Streaming, StreamingDelivery, StreamingDeliveryResult},
     types::{ChannelId, PayloadSize},
     types::{FieldTable, ShortString},
     wakers::Wakers,
-    BasicProperties, Error, Result,
+    BasicProperties, Error, Promise, Result, RetryPolicy,
 };
+use bytes::BytesMut;
 use executor_trait::FullExecutor;
 use flume::{Receiver, Sender};
 use futures_lite::Stream;
@@ -19,7 +22,7 @@ use std::{
     fmt,
     future::Future,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Weak},
     task::{Context, Poll},
 };
 use tracing::trace;
@@ -89,6 +92,11 @@ impl<
 /// To stop receiving messages, call [`Channel::basic_cancel`] with the consumer tag of this
 /// consumer.
 ///
+/// The server can also cancel the consumer, for example when its queue gets deleted or when the
+/// leader of a replicated queue moves to another node. The consumer then yields
+/// [`Error::ConsumerCancelledByServer`] before ending, unless it was set to resubscribe using
+/// [`set_auto_resubscribe`].
+///
 ///
 /// ## Example
 /// ```rust,no_run
@@ -133,6 +141,8 @@ impl<
 /// [`DeliveryResult`]: ./message/type.DeliveryResult.html
 /// [`BasicConsumeOptions::no_ack`]: ./options/struct.BasicConsumeOptions.html#structfield.no_ack
 /// [`set_delegate`]: #method.set_delegate
+/// [`Error::ConsumerCancelledByServer`]: ./enum.Error.html#variant.ConsumerCancelledByServer
+/// [`set_auto_resubscribe`]: #method.set_auto_resubscribe
 #[derive(Clone)]
 pub struct Consumer {
     inner: Arc<Mutex<ConsumerInner>>,
@@ -190,6 +200,13 @@ impl Consumer {
         }
     }
 
+    /// Drop a handle duplicating one which was already given out, without cancelling the consumer
+    pub(crate) fn release(mut self) {
+        if let Some(canceler) = self.consumer_canceler.take() {
+            canceler.disarm();
+        }
+    }
+
     pub(crate) fn error(&self) -> ErrorHolder {
         self.inner.lock().error.clone()
     }
@@ -233,12 +250,89 @@ impl Consumer {
         status.set_delegate();
     }
 
+    /// Override the limits on the deliveries this consumer can buffer, which default to the
+    /// ones set in the [`ConnectionProperties`].
+    ///
+    /// [`ConnectionProperties`]: ./struct.ConnectionProperties.html
+    pub fn set_buffer_limits(&self, limits: BufferLimits) {
+        if let Some(buffer) = self.inner.lock().buffer.as_mut() {
+            buffer.set_limits(limits);
+        }
+    }
+
+    /// Resubscribe when the server cancels this consumer, instead of ending it.
+    ///
+    /// The consumer still yields [`Error::ConsumerCancelledByServer`], then `basic_consume` gets
+    /// issued again with the same queue, tag, options and arguments after the delays of
+    /// `policy`. If we declared the queue on this connection, it gets declared and bound again
+    /// first. Once all the retries failed, the consumer yields the last error and ends.
+    ///
+    /// [`Error::ConsumerCancelledByServer`]: ./enum.Error.html#variant.ConsumerCancelledByServer
+    pub fn set_auto_resubscribe(&self, policy: RetryPolicy) {
+        self.inner.lock().resubscribe = Some(policy);
+    }
+
+    /// The server cancelled this consumer: tell it and check whether we should resubscribe
+    pub(crate) fn server_cancel(&self) -> Option<RetryPolicy> {
+        let mut inner = self.inner.lock();
+        let tag = inner.tag.clone();
+        inner.notify(Error::ConsumerCancelledByServer(tag));
+        let policy = inner
+            .resubscribe
+            .clone()
+            .filter(|_| self.status.state().is_active());
+        inner.resubscribing = policy.is_some();
+        policy
+    }
+
+    pub(crate) fn resubscribing(&self) -> bool {
+        self.inner.lock().resubscribing && self.status.state().is_active()
+    }
+
+    pub(crate) fn resubscribed(&self) {
+        self.inner.lock().resubscribing = false;
+    }
+
+    pub(crate) fn notify_error(&self, error: Error) {
+        self.inner.lock().notify(error);
+    }
+
+    pub(crate) fn set_buffer(&self, buffer: ConsumerBuffer) {
+        self.inner.lock().buffer = Some(buffer);
+    }
+
+    pub(crate) fn set_streaming(&self) {
+        self.inner.lock().streaming = Some(Streaming::default());
+    }
+
+    pub(crate) fn poll_streaming(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<StreamingDelivery>>> {
+        let inner = self.inner.lock();
+        inner.wakers.register(cx.waker());
+        match inner
+            .streaming
+            .as_ref()
+            .and_then(|streaming| streaming.next_delivery())
+        {
+            Some(Ok(Some(delivery))) => Poll::Ready(Some(Ok(delivery))),
+            Some(Ok(None)) => Poll::Ready(None),
+            Some(Err(error)) => Poll::Ready(Some(Err(error))),
+            None => Poll::Pending,
+        }
+    }
+
     pub(crate) fn reset(&self) {
-        self.inner.lock().reset(self.options.no_ack);
+        let dropped = self.inner.lock().reset(self.options.no_ack);
+        // Dropping their body releases it from the buffer, which needs the lock
+        drop(dropped);
     }
 
     pub(crate) fn start_new_delivery(&self, delivery: Delivery) {
-        self.inner.lock().current_message = Some(delivery);
+        let mut inner = self.inner.lock();
+        inner.current_message = Some(delivery);
+        inner.current_payload.clear();
     }
 
     pub(crate) fn handle_content_header_frame(
@@ -246,9 +340,10 @@ impl Consumer {
         size: PayloadSize,
         properties: BasicProperties,
     ) {
+        let releaser = BodyReleaser(Arc::downgrade(&self.inner));
         self.inner
             .lock()
-            .handle_content_header_frame(size, properties);
+            .handle_content_header_frame(size, properties, releaser);
     }
 
     pub(crate) fn handle_body_frame(&self, remaining_size: PayloadSize, payload: Vec<u8>) {
@@ -259,6 +354,10 @@ impl Consumer {
         self.inner.lock().drop_prefetched_messages();
     }
 
+    pub(crate) fn invalidate_deliveries(&self, error: Error) {
+        self.inner.lock().invalidate_deliveries(error);
+    }
+
     pub(crate) fn start_cancel(&self) {
         self.status.lock().start_cancel();
     }
@@ -275,6 +374,7 @@ impl Consumer {
 struct ConsumerInner {
     status: ConsumerStatus,
     current_message: Option<Delivery>,
+    current_payload: BytesMut,
     deliveries_in: Sender<DeliveryResult>,
     deliveries_out: Receiver<DeliveryResult>,
     wakers: Wakers,
@@ -282,6 +382,10 @@ struct ConsumerInner {
     tag: ShortString,
     delegate: Option<Arc<Box<dyn ConsumerDelegate>>>,
     executor: Arc<dyn FullExecutor + Send + Sync>,
+    buffer: Option<ConsumerBuffer>,
+    streaming: Option<Streaming>,
+    resubscribe: Option<RetryPolicy>,
+    resubscribing: bool,
 }
 
 impl fmt::Debug for Consumer {
@@ -307,6 +411,7 @@ impl ConsumerInner {
         Self {
             status,
             current_message: None,
+            current_payload: BytesMut::default(),
             deliveries_in: sender,
             deliveries_out: receiver,
             wakers: Wakers::default(),
@@ -314,21 +419,51 @@ impl ConsumerInner {
             tag: consumer_tag,
             delegate: None,
             executor,
+            buffer: None,
+            streaming: None,
+            resubscribe: None,
+            resubscribing: false,
         }
     }
 
-    fn reset(&mut self, no_ack: bool) {
+    fn reset(&mut self, no_ack: bool) -> Vec<StreamingDeliveryResult> {
+        let mut dropped = Vec::new();
         if !no_ack {
             while self.next_delivery().is_some() {}
         }
+        if let Some(streaming) = self.streaming.as_mut() {
+            streaming.interrupt();
+            if !no_ack {
+                dropped.extend(std::iter::from_fn(|| streaming.next_delivery()));
+            }
+        }
         self.current_message = None;
+        dropped
     }
 
     fn next_delivery(&mut self) -> Option<DeliveryResult> {
-        self.deliveries_out.try_recv().ok()
+        let delivery = self.deliveries_out.try_recv().ok();
+        if let (Some(Ok(Some(delivery))), Some(buffer)) = (&delivery, self.buffer.as_mut()) {
+            buffer.pop(delivery.data.len());
+        }
+        delivery
     }
 
-    fn handle_content_header_frame(&mut self, size: PayloadSize, properties: BasicProperties) {
+    fn handle_content_header_frame(
+        &mut self,
+        size: PayloadSize,
+        properties: BasicProperties,
+        releaser: BodyReleaser,
+    ) {
+        if let Some(streaming) = self.streaming.as_mut() {
+            if let Some(mut delivery) = self.current_message.take() {
+                trace!(consumer_tag=%self.tag, "new streaming delivery");
+                delivery.properties = properties;
+                streaming.start_body(delivery, size, releaser);
+                self.wakers.wake();
+            }
+            return;
+        }
         if let Some(delivery) = self.current_message.as_mut() {
             delivery.properties = properties;
         }
@@ -338,8 +473,18 @@ impl ConsumerInner {
     }
 
     fn handle_body_frame(&mut self, remaining_size: PayloadSize, payload: Vec<u8>) {
+        if let Some(streaming) = self.streaming.as_mut() {
+            let size = payload.len();
+            // Account for the chunks until they get read, for reading to pause when needed
+            if streaming.receive_body(payload, remaining_size) {
+                if let Some(buffer) = self.buffer.as_mut() {
+                    buffer.push_chunk(size);
+                }
+            }
+            return;
+        }
         if let Some(delivery) = self.current_message.as_mut() {
-            delivery.receive_content(payload);
+            delivery.receive_content(payload, remaining_size, &mut self.current_payload);
         }
         if remaining_size == 0 {
             self.new_delivery_complete();
@@ -353,15 +498,44 @@ impl ConsumerInner {
                 let delegate = delegate.clone();
                 self.executor
                     .spawn(delegate.on_new_delivery(Ok(Some(delivery))));
-            } else {
+            } else if self.accept_delivery(&delivery) {
                 self.deliveries_in
                     .send(Ok(Some(delivery)))
                     .expect("failed to send delivery to consumer");
+            } else {
+                self.refuse_delivery(delivery);
             }
             self.wakers.wake();
         }
     }
 
+    fn accept_delivery(&mut self, delivery: &Delivery) -> bool {
+        match self.buffer.as_mut() {
+            Some(buffer) => buffer.push(delivery.data.len()),
+            None => true,
+        }
+    }
+
+    fn refuse_delivery(&mut self, delivery: Delivery) {
+        trace!(consumer_tag=%self.tag, delivery_tag=%delivery.delivery_tag, "buffer full, requeuing delivery");
+        if let Some(buffer) = self.buffer.as_ref().filter(|buffer| !buffer.no_ack()) {
+            let (_, resolver) = Promise::new();
+            buffer.internal_rpc().basic_reject(
+                buffer.channel_id(),
+                delivery.delivery_tag,
+                BasicRejectOptions { requeue: true },
+                resolver,
+                Some(self.error.clone()),
+            );
+        }
+        // Only report the buffer getting full once, until it accepts deliveries again
+        if self.buffer.as_mut().map_or(false, ConsumerBuffer::refuse) {
+            self.deliveries_in
+                .send(Err(Error::ConsumerBufferFull(self.tag.clone())))
+                .expect("failed to send error to consumer");
+        }
+    }
+
     fn drop_prefetched_messages(&mut self) {
         trace!(consumer_tag=%self.tag, "drop_prefetched_messages");
         if let Some(delegate) = self.delegate.as_ref() {
@@ -371,12 +545,27 @@ impl ConsumerInner {
         while self.next_delivery().is_some() {}
     }
 
+    fn invalidate_deliveries(&mut self, error: Error) {
+        trace!(consumer_tag=%self.tag, "invalidate_deliveries");
+        // Deliveries we already handed out cannot be acked anymore as their delivery tags belonged
+        // to the previous channel, make their Acker fail and start over with a new ErrorHolder.
+        self.error.set(error);
+        self.error = ErrorHolder::default();
+        self.current_message = None;
+        if let Some(streaming) = self.streaming.as_mut() {
+            streaming.interrupt();
+        }
+    }
+
     fn cancel(&mut self) {
         trace!(consumer_tag=%self.tag, "cancel");
         let mut status = self.status.lock();
         if let Some(delegate) = self.delegate.as_ref() {
             let delegate = delegate.clone();
             self.executor.spawn(delegate.on_new_delivery(Ok(None)));
+        } else if let Some(streaming) = self.streaming.as_mut() {
+            streaming.interrupt();
+            streaming.send(Ok(None));
         } else {
             self.deliveries_in
                 .send(Ok(None))
@@ -389,15 +578,47 @@ impl ConsumerInner {
     fn set_error(&mut self, error: Error) {
         trace!(consumer_tag=%self.tag, "set_error");
         self.error.set(error.clone());
+        self.notify(error);
+        self.cancel();
+    }
+
+    fn notify(&mut self, error: Error) {
         if let Some(delegate) = self.delegate.as_ref() {
             let delegate = delegate.clone();
             self.executor.spawn(delegate.on_new_delivery(Err(error)));
+        } else if let Some(streaming) = self.streaming.as_ref() {
+            streaming.send(Err(error));
         } else {
             self.deliveries_in
                 .send(Err(error))
                 .expect("failed to send error to consumer");
         }
-        self.cancel();
+        self.wakers.wake();
+    }
+}
+
+/// Releases the body chunks of a streaming delivery from its consumer's buffer once read
+pub(crate) struct BodyReleaser(Weak<Mutex<ConsumerInner>>);
+
+impl BodyReleaser {
+    pub(crate) fn release(&self, size: usize) {
+        self.release_with(|| size);
+    }
+
+    /// Release the chunks counted by `chunks`, no new chunk being received in the meantime
+    pub(crate) fn release_with(&self, chunks: impl FnOnce() -> usize) {
+        match self.0.upgrade() {
+            Some(inner) => {
+                let mut inner = inner.lock();
+                let size = chunks();
+                if let Some(buffer) = inner.buffer.as_mut() {
+                    buffer.pop_chunk(size);
+                }
+            }
+            None => {
+                chunks();
+            }
+        }
     }
 }
 
@@ -445,6 +666,7 @@ mod futures_tests {
     };
     use std::task::{Context, Poll};
 
+    use crate::streaming::StreamingConsumer;
     use futures_lite::stream::StreamExt;
     use waker_fn::waker_fn;
 
@@ -522,4 +744,257 @@ mod futures_tests {
             );
         }
     }
+
+    use crate::{
+        consumer_buffer::BufferOverflowPolicy,
+        internal_rpc::{InternalCommand, InternalRPC},
+        socket_state::SocketState,
+        types::DeliveryTag,
+        Configuration,
+    };
+
+    /// A consumer on channel 1 buffering up to two deliveries, with the given policy
+    fn buffered_consumer(policy: BufferOverflowPolicy) -> (Consumer, Configuration, InternalRPC) {
+        let executor = Arc::new(async_global_executor_trait::AsyncGlobalExecutor);
+        let socket_state = SocketState::default();
+        let internal_rpc = InternalRPC::new(executor.clone(), socket_state.handle());
+        let configuration = Configuration::default();
+        configuration.set_consumer_buffer_limits(BufferLimits::default().with_max_messages(2));
+        configuration.set_buffer_overflow_policy(policy);
+
+        let consumer = Consumer::new(
+            ShortString::from("test-consumer"),
+            executor,
+            None,
+            "test".into(),
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        );
+        consumer.set_buffer(ConsumerBuffer::new(
+            1,
+            false,
+            configuration.clone(),
+            internal_rpc.handle(),
+            socket_state.handle(),
+        ));
+        (consumer, configuration, internal_rpc)
+    }
+
+    fn deliver(consumer: &Consumer, delivery_tag: DeliveryTag) {
+        consumer.start_new_delivery(Delivery::new(
+            1,
+            delivery_tag,
+            "".into(),
+            "test".into(),
+            false,
+            None,
+            None,
+        ));
+        consumer.handle_content_header_frame(0, BasicProperties::default());
+    }
+
+    fn poll_next(consumer: &mut Consumer) -> Poll<Option<Result<Delivery>>> {
+        let waker = waker_fn(|| {});
+        let mut cx = Context::from_waker(&waker);
+        Pin::new(consumer).poll_next(&mut cx)
+    }
+
+    #[test]
+    fn stream_pauses_reading_when_buffer_full() {
+        let (mut consumer, configuration, _internal_rpc) =
+            buffered_consumer(BufferOverflowPolicy::PauseReading);
+
+        deliver(&consumer, 1);
+        assert!(!configuration.buffer_usage().reading_paused());
+        deliver(&consumer, 2);
+        assert!(configuration.buffer_usage().reading_paused());
+
+        assert!(matches!(poll_next(&mut consumer), Poll::Ready(Some(Ok(_)))));
+        assert!(!configuration.buffer_usage().reading_paused());
+    }
+
+    #[test]
+    fn stream_pauses_channel_when_buffer_full() {
+        let (mut consumer, configuration, internal_rpc) =
+            buffered_consumer(BufferOverflowPolicy::ChannelFlow);
+
+        deliver(&consumer, 1);
+        assert!(internal_rpc.try_recv().is_none());
+        deliver(&consumer, 2);
+        assert!(matches!(
+            internal_rpc.try_recv(),
+            Some(InternalCommand::SetChannelFlow(1, false))
+        ));
+        // The server may still send a few deliveries before handling channel.flow
+        deliver(&consumer, 3);
+        assert!(internal_rpc.try_recv().is_none());
+        assert!(!configuration.buffer_usage().reading_paused());
+
+        assert!(matches!(poll_next(&mut consumer), Poll::Ready(Some(Ok(_)))));
+        assert!(internal_rpc.try_recv().is_none());
+        assert!(matches!(poll_next(&mut consumer), Poll::Ready(Some(Ok(_)))));
+        assert!(matches!(
+            internal_rpc.try_recv(),
+            Some(InternalCommand::SetChannelFlow(1, true))
+        ));
+    }
+
+    #[test]
+    fn stream_fails_when_buffer_full() {
+        let (mut consumer, _configuration, internal_rpc) =
+            buffered_consumer(BufferOverflowPolicy::FailConsumer);
+        let assert_rejected = |tag| {
+            assert!(matches!(
+                internal_rpc.try_recv(),
+                Some(InternalCommand::BasicReject(1, delivery_tag, BasicRejectOptions { requeue: true }, _, _)) if delivery_tag == tag
+            ));
+        };
+        let buffer_full = Error::ConsumerBufferFull("test-consumer".into());
+
+        for delivery_tag in 1..=5 {
+            deliver(&consumer, delivery_tag);
+        }
+        assert_rejected(3);
+        assert_rejected(4);
+        assert_rejected(5);
+        assert!(internal_rpc.try_recv().is_none());
+
+        // A single error for the whole time the buffer stayed full
+        assert!(matches!(poll_next(&mut consumer), Poll::Ready(Some(Ok(_)))));
+        assert!(matches!(poll_next(&mut consumer), Poll::Ready(Some(Ok(_)))));
+        assert_eq!(
+            poll_next(&mut consumer),
+            Poll::Ready(Some(Err(buffer_full.clone())))
+        );
+        assert_eq!(poll_next(&mut consumer), Poll::Pending);
+
+        // Once the buffer accepted deliveries again, getting full gets reported again
+        for delivery_tag in 6..=9 {
+            deliver(&consumer, delivery_tag);
+        }
+        assert_rejected(8);
+        assert_rejected(9);
+        assert!(matches!(poll_next(&mut consumer), Poll::Ready(Some(Ok(_)))));
+        assert!(matches!(poll_next(&mut consumer), Poll::Ready(Some(Ok(_)))));
+        assert_eq!(
+            poll_next(&mut consumer),
+            Poll::Ready(Some(Err(buffer_full)))
+        );
+        assert_eq!(poll_next(&mut consumer), Poll::Pending);
+    }
+
+    #[test]
+    fn streaming_body() {
+        use futures_lite::{future, AsyncReadExt};
+
+        let mut consumer = StreamingConsumer::new(Consumer::new(
+            ShortString::from("test-consumer"),
+            Arc::new(async_global_executor_trait::AsyncGlobalExecutor),
+            None,
+            "test".into(),
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        ));
+        let inner = consumer.consumer().clone();
+        inner.set_streaming();
+        inner.start_new_delivery(Delivery::new(
+            1,
+            1,
+            "".into(),
+            "test".into(),
+            false,
+            None,
+            None,
+        ));
+        inner.handle_content_header_frame(5, BasicProperties::default());
+
+        let mut delivery = future::block_on(consumer.next())
+            .expect("no delivery")
+            .expect("delivery failed");
+        assert_eq!(delivery.body_size, 5);
+        assert!(delivery.delivery.data.is_empty());
+
+        inner.handle_body_frame(2, b"abc".to_vec());
+        inner.handle_body_frame(0, b"de".to_vec());
+        let mut body = Vec::new();
+        future::block_on(delivery.body.read_to_end(&mut body)).expect("read failed");
+        assert_eq!(body, b"abcde");
+    }
+
+    #[test]
+    fn streaming_body_pauses_reading() {
+        use futures_lite::{future, AsyncReadExt};
+
+        let (consumer, configuration, _internal_rpc) =
+            buffered_consumer(BufferOverflowPolicy::PauseReading);
+        consumer.set_buffer_limits(BufferLimits::default().with_max_bytes(4));
+        consumer.set_streaming();
+        let mut streaming = StreamingConsumer::new(consumer.clone());
+        consumer.start_new_delivery(Delivery::new(
+            1,
+            1,
+            "".into(),
+            "test".into(),
+            false,
+            None,
+            None,
+        ));
+        consumer.handle_content_header_frame(9, BasicProperties::default());
+        let mut delivery = future::block_on(streaming.next())
+            .expect("no delivery")
+            .expect("delivery failed");
+
+        consumer.handle_body_frame(6, b"abc".to_vec());
+        assert!(!configuration.buffer_usage().reading_paused());
+        consumer.handle_body_frame(3, b"def".to_vec());
+        assert!(configuration.buffer_usage().reading_paused());
+
+        // Reading resumes once enough of the body got drained
+        let mut body = [0; 2];
+        future::block_on(delivery.body.read_exact(&mut body)).expect("read failed");
+        assert!(!configuration.buffer_usage().reading_paused());
+        consumer.handle_body_frame(0, b"ghi".to_vec());
+        assert!(configuration.buffer_usage().reading_paused());
+
+        // Dropping the body forgets about its unread chunks
+        drop(delivery);
+        assert!(!configuration.buffer_usage().reading_paused());
+    }
+
+    #[test]
+    fn server_cancel() {
+        use futures_lite::{future, StreamExt};
+
+        let new_consumer = || {
+            Consumer::new(
+                ShortString::from("test-consumer"),
+                Arc::new(async_global_executor_trait::AsyncGlobalExecutor),
+                None,
+                "test".into(),
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+        };
+        let cancelled_by_server = Error::ConsumerCancelledByServer("test-consumer".into());
+
+        let mut consumer = new_consumer();
+        assert_eq!(consumer.server_cancel(), None);
+        consumer.cancel();
+        assert_eq!(
+            future::block_on(consumer.next()).map(|res| res.err()),
+            Some(Some(cancelled_by_server.clone()))
+        );
+        assert!(future::block_on(consumer.next()).is_none());
+
+        let mut consumer = new_consumer();
+        consumer.set_auto_resubscribe(RetryPolicy::default());
+        assert_eq!(consumer.server_cancel(), Some(RetryPolicy::default()));
+        assert!(consumer.resubscribing());
+        assert_eq!(
+            future::block_on(consumer.next()).map(|res| res.err()),
+            Some(Some(cancelled_by_server))
+        );
+        consumer.resubscribed();
+        assert!(!consumer.resubscribing());
+    }
 }
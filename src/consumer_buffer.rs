@@ -0,0 +1,287 @@
+use crate::{
+    internal_rpc::InternalRPCHandle, socket_state::SocketStateHandle, types::ChannelId,
+    Configuration,
+};
+use parking_lot::{Mutex, MutexGuard};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Arc,
+};
+use tracing::trace;
+
+/// Limits on the deliveries which were received but not consumed yet.
+///
+/// Set them per connection and per consumer through [`ConnectionProperties`], the latter
+/// being overridable with [`Consumer::set_buffer_limits`]. Once a limit is reached, the
+/// [`BufferOverflowPolicy`] applies.
+///
+/// [`ConnectionProperties`]: ./struct.ConnectionProperties.html
+/// [`Consumer::set_buffer_limits`]: ./struct.Consumer.html#method.set_buffer_limits
+/// [`BufferOverflowPolicy`]: ./enum.BufferOverflowPolicy.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BufferLimits {
+    /// How many deliveries can be buffered, unlimited if `None`
+    pub max_messages: Option<usize>,
+    /// How many bytes of payload can be buffered, unlimited if `None`
+    pub max_bytes: Option<usize>,
+}
+
+impl BufferLimits {
+    pub fn with_max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = Some(max_messages);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    fn reached(&self, messages: usize, bytes: usize) -> bool {
+        self.max_messages.map_or(false, |max| messages >= max)
+            || self.max_bytes.map_or(false, |max| bytes >= max)
+    }
+}
+
+/// What happens once consumers buffered as many deliveries as their [`BufferLimits`] allow.
+///
+/// Deliveries handed to a [`ConsumerDelegate`] aren't buffered and don't count.
+///
+/// [`BufferLimits`]: ./struct.BufferLimits.html
+/// [`ConsumerDelegate`]: ./trait.ConsumerDelegate.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferOverflowPolicy {
+    /// Stop reading from the socket until enough deliveries got consumed.
+    ///
+    /// Nothing else gets received on the connection in the meantime, including the replies to
+    /// our requests.
+    PauseReading,
+    /// Ask the server to pause the channel using `channel.flow` until enough deliveries got
+    /// consumed. RabbitMQ doesn't support this.
+    ChannelFlow,
+    /// Don't buffer the new deliveries: requeue them and send [`Error::ConsumerBufferFull`] to
+    /// the consumer instead
+    ///
+    /// [`Error::ConsumerBufferFull`]: ./enum.Error.html#variant.ConsumerBufferFull
+    FailConsumer,
+}
+
+impl Default for BufferOverflowPolicy {
+    fn default() -> Self {
+        Self::PauseReading
+    }
+}
+
+/// What all the consumers of a connection buffered
+#[derive(Clone, Default)]
+pub(crate) struct BufferUsage(Arc<Mutex<Usage>>);
+
+#[derive(Default)]
+struct Usage {
+    messages: usize,
+    bytes: usize,
+    // How many consumers reached their own limits, by channel
+    full_consumers: HashMap<ChannelId, usize>,
+    paused_channels: HashSet<ChannelId>,
+    reading_paused: bool,
+}
+
+impl BufferUsage {
+    pub(crate) fn reading_paused(&self) -> bool {
+        self.0.lock().reading_paused
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Usage> {
+        self.0.lock()
+    }
+}
+
+impl fmt::Debug for BufferUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("BufferUsage");
+        if let Some(usage) = self.0.try_lock() {
+            debug
+                .field("messages", &usage.messages)
+                .field("bytes", &usage.bytes)
+                .field("paused_channels", &usage.paused_channels)
+                .field("reading_paused", &usage.reading_paused);
+        }
+        debug.finish()
+    }
+}
+
+/// The accounting of the deliveries buffered by one consumer
+pub(crate) struct ConsumerBuffer {
+    channel_id: ChannelId,
+    no_ack: bool,
+    limits: BufferLimits,
+    messages: usize,
+    bytes: usize,
+    full: bool,
+    refusing: bool,
+    configuration: Configuration,
+    internal_rpc: InternalRPCHandle,
+    waker: SocketStateHandle,
+}
+
+impl ConsumerBuffer {
+    pub(crate) fn new(
+        channel_id: ChannelId,
+        no_ack: bool,
+        configuration: Configuration,
+        internal_rpc: InternalRPCHandle,
+        waker: SocketStateHandle,
+    ) -> Self {
+        Self {
+            channel_id,
+            no_ack,
+            limits: configuration.consumer_buffer_limits(),
+            messages: 0,
+            bytes: 0,
+            full: false,
+            refusing: false,
+            configuration,
+            internal_rpc,
+            waker,
+        }
+    }
+
+    pub(crate) fn no_ack(&self) -> bool {
+        self.no_ack
+    }
+
+    pub(crate) fn channel_id(&self) -> ChannelId {
+        self.channel_id
+    }
+
+    pub(crate) fn internal_rpc(&self) -> &InternalRPCHandle {
+        &self.internal_rpc
+    }
+
+    pub(crate) fn set_limits(&mut self, limits: BufferLimits) {
+        self.limits = limits;
+        let usage = self.configuration.buffer_usage().clone();
+        self.update(&mut usage.lock());
+    }
+
+    /// Account for a new delivery of `size` bytes, unless it must be refused
+    pub(crate) fn push(&mut self, size: usize) -> bool {
+        let usage = self.configuration.buffer_usage().clone();
+        let mut usage = usage.lock();
+        if self.configuration.buffer_overflow_policy() == BufferOverflowPolicy::FailConsumer
+            && (self.limits.reached(self.messages, self.bytes)
+                || self
+                    .configuration
+                    .connection_buffer_limits()
+                    .reached(usage.messages, usage.bytes))
+        {
+            return false;
+        }
+        self.refusing = false;
+        self.messages += 1;
+        self.bytes += size;
+        usage.messages += 1;
+        usage.bytes += size;
+        self.update(&mut usage);
+        true
+    }
+
+    /// Account for a body chunk of `size` bytes of a streaming delivery, which can't be refused
+    pub(crate) fn push_chunk(&mut self, size: usize) {
+        let usage = self.configuration.buffer_usage().clone();
+        let mut usage = usage.lock();
+        self.bytes += size;
+        usage.bytes += size;
+        self.update(&mut usage);
+    }
+
+    /// A body chunk of `size` bytes of a streaming delivery got read
+    pub(crate) fn pop_chunk(&mut self, size: usize) {
+        self.release(0, size);
+    }
+
+    /// A delivery got refused, returns whether it's the first one since the buffer got full
+    pub(crate) fn refuse(&mut self) -> bool {
+        !std::mem::replace(&mut self.refusing, true)
+    }
+
+    /// A delivery of `size` bytes got consumed
+    pub(crate) fn pop(&mut self, size: usize) {
+        self.release(1, size);
+    }
+
+    fn release(&mut self, messages: usize, bytes: usize) {
+        let usage = self.configuration.buffer_usage().clone();
+        let mut usage = usage.lock();
+        self.messages -= messages;
+        self.bytes -= bytes;
+        usage.messages -= messages;
+        usage.bytes -= bytes;
+        self.update(&mut usage);
+    }
+
+    fn update(&mut self, usage: &mut Usage) {
+        let full = self.limits.reached(self.messages, self.bytes);
+        if full != self.full {
+            self.full = full;
+            let count = usage.full_consumers.entry(self.channel_id).or_default();
+            if full {
+                *count += 1;
+            } else {
+                *count -= 1;
+                if *count == 0 {
+                    usage.full_consumers.remove(&self.channel_id);
+                }
+            }
+        }
+        let connection_full = self
+            .configuration
+            .connection_buffer_limits()
+            .reached(usage.messages, usage.bytes);
+        match self.configuration.buffer_overflow_policy() {
+            BufferOverflowPolicy::PauseReading => {
+                let paused = connection_full || !usage.full_consumers.is_empty();
+                if paused != usage.reading_paused {
+                    trace!(
+                        paused,
+                        "Consumer buffers reached their limits, toggling reading"
+                    );
+                    usage.reading_paused = paused;
+                    if !paused {
+                        self.waker.wake();
+                    }
+                }
+            }
+            BufferOverflowPolicy::ChannelFlow => {
+                if (connection_full || usage.full_consumers.contains_key(&self.channel_id))
+                    && usage.paused_channels.insert(self.channel_id)
+                {
+                    trace!(channel=%self.channel_id, "Consumer buffers reached their limits, pausing channel");
+                    self.internal_rpc.set_channel_flow(self.channel_id, false);
+                }
+                if !connection_full {
+                    let full_consumers = &usage.full_consumers;
+                    let internal_rpc = &self.internal_rpc;
+                    usage.paused_channels.retain(|channel_id| {
+                        let paused = full_consumers.contains_key(channel_id);
+                        if !paused {
+                            trace!(channel=%channel_id, "Consumer buffers drained, resuming channel");
+                            internal_rpc.set_channel_flow(*channel_id, true);
+                        }
+                        paused
+                    });
+                }
+            }
+            BufferOverflowPolicy::FailConsumer => {}
+        }
+    }
+}
+
+impl Drop for ConsumerBuffer {
+    fn drop(&mut self) {
+        // Forget about the deliveries which will never be consumed
+        self.limits = BufferLimits::default();
+        self.release(self.messages, self.bytes);
+    }
+}
@@ -3,12 +3,14 @@ use crate::{
     internal_rpc::InternalRPCHandle,
     types::ChannelId,
 };
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub(crate) struct ConsumerCanceler {
     channel_id: ChannelId,
     consumer_tag: String,
     status: ConsumerStatus,
     internal_rpc: InternalRPCHandle,
+    disarmed: AtomicBool,
 }
 
 impl ConsumerCanceler {
@@ -23,12 +25,21 @@ impl ConsumerCanceler {
             consumer_tag,
             status,
             internal_rpc,
+            disarmed: AtomicBool::new(false),
         }
     }
+
+    /// Don't cancel the consumer when dropped, another handle being in charge of it
+    pub(crate) fn disarm(&self) {
+        self.disarmed.store(true, Ordering::SeqCst);
+    }
 }
 
 impl Drop for ConsumerCanceler {
     fn drop(&mut self) {
+        if self.disarmed.load(Ordering::SeqCst) {
+            return;
+        }
         let status = self.status.lock();
         if status.state() == ConsumerState::Active {
             self.internal_rpc.cancel_consumer(
@@ -0,0 +1,219 @@
+use crate::{
+    acker::Acker,
+    in_flight::{InFlight, Permit},
+    message::Delivery,
+    options::{BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicQosOptions},
+    types::{FieldTable, ShortUInt},
+    Channel, Consumer, Result,
+};
+use futures_lite::{FutureExt, StreamExt};
+use std::{convert::TryFrom, fmt, future::Future, panic::AssertUnwindSafe, sync::Arc};
+use tracing::{error, trace, warn};
+
+/// What to do with a delivery once it got handled by a [`ConsumerRunner`]
+///
+/// [`ConsumerRunner`]: ./struct.ConsumerRunner.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Ack the delivery
+    Ack,
+    /// Nack the delivery, requeuing it
+    Requeue,
+    /// Nack the delivery without requeuing it, so that it gets dead-lettered if the queue is
+    /// configured to
+    DeadLetter,
+}
+
+/// What a [`ConsumerRunner`] does with a delivery when its handler fails or panics
+///
+/// [`ConsumerRunner`]: ./struct.ConsumerRunner.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Requeue the delivery
+    Requeue,
+    /// Dead-letter the delivery
+    DeadLetter,
+    /// Requeue the delivery the first time, dead-letter it if it was already redelivered
+    RequeueOnce,
+}
+
+impl Default for FailurePolicy {
+    fn default() -> Self {
+        Self::RequeueOnce
+    }
+}
+
+impl FailurePolicy {
+    fn outcome(self, redelivered: bool) -> Outcome {
+        match self {
+            FailurePolicy::Requeue => Outcome::Requeue,
+            FailurePolicy::DeadLetter => Outcome::DeadLetter,
+            FailurePolicy::RequeueOnce if redelivered => Outcome::DeadLetter,
+            FailurePolicy::RequeueOnce => Outcome::Requeue,
+        }
+    }
+}
+
+/// Runs an async handler on the deliveries of a consumer, acking or nacking them according to
+/// its result.
+///
+/// At most `max_in_flight` deliveries are handled at the same time, and the prefetch count of
+/// the channel is set accordingly using `basic_qos` so that the server doesn't send more.
+#[derive(Clone)]
+pub struct ConsumerRunner {
+    channel: Channel,
+    max_in_flight: usize,
+    failure_policy: FailurePolicy,
+}
+
+impl ConsumerRunner {
+    /// Create a runner consuming on `channel`, handling at most `max_in_flight` deliveries at
+    /// the same time
+    pub fn new(channel: &Channel, max_in_flight: usize) -> Self {
+        Self {
+            channel: channel.clone(),
+            max_in_flight: max_in_flight.max(1),
+            failure_policy: FailurePolicy::default(),
+        }
+    }
+
+    /// What to do with the deliveries for which the handler fails, see [`FailurePolicy`].
+    ///
+    /// [`FailurePolicy`]: ./enum.FailurePolicy.html
+    pub fn with_failure_policy(mut self, failure_policy: FailurePolicy) -> Self {
+        self.failure_policy = failure_policy;
+        self
+    }
+
+    /// Consume `queue` and run `handler` on each delivery until the consumer gets cancelled,
+    /// then wait for the ongoing ones.
+    ///
+    /// The deliveries are acked or nacked according to the [`Outcome`] returned by `handler`, or
+    /// according to the [`FailurePolicy`] if it fails or panics. The consumer is never in no-ack
+    /// mode, whatever `options` say. This returns an error if the consumer failed.
+    ///
+    /// [`Outcome`]: ./enum.Outcome.html
+    /// [`FailurePolicy`]: ./enum.FailurePolicy.html
+    pub async fn run<H, F, E>(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+        options: BasicConsumeOptions,
+        arguments: FieldTable,
+        handler: H,
+    ) -> Result<()>
+    where
+        H: Fn(Delivery) -> F + Send + Sync + 'static,
+        F: Future<Output = std::result::Result<Outcome, E>> + Send + 'static,
+        E: fmt::Display + Send,
+    {
+        let prefetch_count = ShortUInt::try_from(self.max_in_flight).unwrap_or(ShortUInt::MAX);
+        self.channel
+            .basic_qos(prefetch_count, BasicQosOptions::default())
+            .await?;
+        let consumer = self
+            .channel
+            .basic_consume(
+                queue,
+                consumer_tag,
+                BasicConsumeOptions {
+                    no_ack: false,
+                    ..options
+                },
+                arguments,
+            )
+            .await?;
+        self.run_consumer(consumer, handler).await
+    }
+
+    async fn run_consumer<H, F, E>(&self, mut consumer: Consumer, handler: H) -> Result<()>
+    where
+        H: Fn(Delivery) -> F + Send + Sync + 'static,
+        F: Future<Output = std::result::Result<Outcome, E>> + Send + 'static,
+        E: fmt::Display + Send,
+    {
+        let handler = Arc::new(handler);
+        let in_flight = InFlight::new(self.max_in_flight);
+        let mut res = Ok(());
+        while let Some(delivery) = consumer.next().await {
+            let delivery = match delivery {
+                Ok(delivery) => delivery,
+                Err(error) => {
+                    res = Err(error);
+                    break;
+                }
+            };
+            let task = Task {
+                _permit: in_flight.acquire().await,
+                failure_policy: self.failure_policy,
+            };
+            let handler = handler.clone();
+            self.channel.executor().spawn(Box::pin(async move {
+                task.handle(delivery, &*handler).await;
+            }));
+        }
+        in_flight.wait_idle().await;
+        res
+    }
+}
+
+impl fmt::Debug for ConsumerRunner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConsumerRunner")
+            .field("channel", &self.channel.id())
+            .field("max_in_flight", &self.max_in_flight)
+            .field("failure_policy", &self.failure_policy)
+            .finish()
+    }
+}
+
+struct Task {
+    _permit: Permit,
+    failure_policy: FailurePolicy,
+}
+
+impl Task {
+    async fn handle<H, F, E>(self, delivery: Delivery, handler: &H)
+    where
+        H: Fn(Delivery) -> F + Sync,
+        F: Future<Output = std::result::Result<Outcome, E>> + Send,
+        E: fmt::Display + Send,
+    {
+        let acker = delivery.acker.clone();
+        let delivery_tag = delivery.delivery_tag;
+        let redelivered = delivery.redelivered;
+        let outcome = match AssertUnwindSafe(async { handler(delivery).await })
+            .catch_unwind()
+            .await
+        {
+            Ok(Ok(outcome)) => outcome,
+            Ok(Err(error)) => {
+                warn!(delivery_tag, %error, "Consumer handler failed");
+                self.failure_policy.outcome(redelivered)
+            }
+            Err(_) => {
+                error!(delivery_tag, "Consumer handler panicked");
+                self.failure_policy.outcome(redelivered)
+            }
+        };
+        trace!(delivery_tag, ?outcome, "Settling delivery");
+        if let Err(error) = settle(&acker, outcome).await {
+            warn!(delivery_tag, %error, "Failed to settle delivery");
+        }
+    }
+}
+
+async fn settle(acker: &Acker, outcome: Outcome) -> Result<()> {
+    match outcome {
+        Outcome::Ack => acker.ack(BasicAckOptions::default()).await,
+        Outcome::Requeue => {
+            acker
+                .nack(BasicNackOptions {
+                    requeue: true,
+                    ..BasicNackOptions::default()
+                })
+                .await
+        }
+        Outcome::DeadLetter => acker.nack(BasicNackOptions::default()).await,
+    }
+}
@@ -4,7 +4,7 @@ use crate::{
     message::Delivery,
     topology_internal::ConsumerDefinitionInternal,
     types::{PayloadSize, ShortString},
-    BasicProperties, Error,
+    BasicProperties, Error, RetryPolicy,
 };
 use parking_lot::Mutex;
 use std::{borrow::Borrow, collections::HashMap, fmt, hash::Hash, sync::Arc};
@@ -26,6 +26,25 @@ impl Consumers {
         }
     }
 
+    /// The server cancelled a consumer: deregister it unless it has to resubscribe
+    pub(crate) fn server_cancel<S: Hash + Eq + ?Sized>(
+        &self,
+        consumer_tag: &S,
+    ) -> Option<(Consumer, RetryPolicy)>
+    where
+        ShortString: Borrow<S>,
+    {
+        let mut consumers = self.0.lock();
+        let consumer = consumers.get(consumer_tag)?;
+        if let Some(policy) = consumer.server_cancel() {
+            return Some((consumer.clone(), policy));
+        }
+        if let Some(consumer) = consumers.remove(consumer_tag) {
+            consumer.cancel();
+        }
+        None
+    }
+
     pub(crate) fn start_cancel_one<S: Hash + Eq + ?Sized>(&self, consumer_tag: &S)
     where
         ShortString: Borrow<S>,
@@ -35,6 +54,16 @@ impl Consumers {
         }
     }
 
+    pub(crate) fn no_ack<S: Hash + Eq + ?Sized>(&self, consumer_tag: &S) -> Option<bool>
+    where
+        ShortString: Borrow<S>,
+    {
+        self.0
+            .lock()
+            .get(consumer_tag)
+            .map(|consumer| consumer.options().no_ack)
+    }
+
     pub(crate) fn start_delivery<S: Hash + Eq + ?Sized, F: FnOnce(ErrorHolder) -> Delivery>(
         &self,
         consumer_tag: &S,
@@ -73,6 +102,12 @@ impl Consumers {
         }
     }
 
+    pub(crate) fn invalidate_deliveries(&self, error: Error) {
+        for consumer in self.0.lock().values() {
+            consumer.invalidate_deliveries(error.clone());
+        }
+    }
+
     pub(crate) fn drop_prefetched_messages(&self) {
         for consumer in self.0.lock().values() {
             consumer.drop_prefetched_messages();
@@ -97,6 +132,10 @@ impl Consumers {
         }
     }
 
+    pub(crate) fn tags(&self) -> Vec<ShortString> {
+        self.0.lock().keys().cloned().collect()
+    }
+
     pub(crate) fn topology(&self) -> Vec<ConsumerDefinitionInternal> {
         self.0
             .lock()
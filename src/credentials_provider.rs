@@ -0,0 +1,139 @@
+use crate::{
+    auth::Credentials, connection::WeakConnection, uri::AMQPUri, ConnectionProperties, Result,
+};
+use async_trait::async_trait;
+use executor_trait::FullExecutor;
+use reactor_trait::Reactor;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::{debug, warn};
+
+/// Supplies the credentials used to authenticate against the server.
+///
+/// The provider is queried each time we connect or reconnect. When the returned credentials
+/// expire, the secret is proactively refreshed through [`Connection::update_secret`] before it
+/// lapses, which is what short-lived OAuth2 tokens need. Refresh failures are reported to the
+/// handler set with [`Connection::on_error`].
+///
+/// [`Connection::update_secret`]: ./struct.Connection.html#method.update_secret
+/// [`Connection::on_error`]: ./struct.Connection.html#method.on_error
+#[async_trait]
+pub trait CredentialsProvider: Send + Sync {
+    /// Fetch fresh credentials
+    async fn credentials(&self) -> Result<ExpiringCredentials>;
+}
+
+/// Credentials returned by a [`CredentialsProvider`]
+///
+/// [`CredentialsProvider`]: ./trait.CredentialsProvider.html
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpiringCredentials {
+    /// The username and password (or token) to use
+    pub credentials: Credentials,
+    /// How long these credentials stay valid, `None` if they never expire
+    pub expires_in: Option<Duration>,
+}
+
+/// Get the credentials to use for `uri`, along with their lifetime
+pub(crate) async fn fetch_credentials(
+    uri: &AMQPUri,
+    options: &ConnectionProperties,
+) -> Result<ExpiringCredentials> {
+    match options.credentials_provider.as_ref() {
+        Some(provider) => provider.credentials().await,
+        None => Ok(ExpiringCredentials {
+            credentials: uri.authority.userinfo.clone().into(),
+            expires_in: None,
+        }),
+    }
+}
+
+/// Keeps the secret of a connection up to date, starting over from the credentials of each
+/// successful handshake, including the ones performed while recovering the connection.
+#[derive(Clone)]
+pub(crate) struct SecretRefresh {
+    provider: Option<Arc<dyn CredentialsProvider>>,
+    generation: Arc<AtomicUsize>,
+    executor: Arc<dyn FullExecutor + Send + Sync>,
+    reactor: Arc<dyn Reactor + Send + Sync>,
+}
+
+impl SecretRefresh {
+    pub(crate) fn new(
+        options: &ConnectionProperties,
+        executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn Reactor + Send + Sync>,
+    ) -> Self {
+        Self {
+            provider: options.credentials_provider.clone(),
+            generation: Arc::default(),
+            executor,
+            reactor,
+        }
+    }
+
+    /// Refresh the secret of `connection` before credentials expiring in `expires_in` lapse,
+    /// replacing any refresh started for a previous handshake
+    pub(crate) fn start(&self, connection: WeakConnection, expires_in: Option<Duration>) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let (provider, expires_in) = match (self.provider.clone(), expires_in) {
+            (Some(provider), Some(expires_in)) => (provider, expires_in),
+            _ => return,
+        };
+        let current = self.generation.clone();
+        let reactor = self.reactor.clone();
+        self.executor.spawn(Box::pin(async move {
+            let mut expires_in = expires_in;
+            let mut delay = refresh_delay(expires_in);
+            loop {
+                reactor.sleep(delay).await;
+                if current.load(Ordering::SeqCst) != generation {
+                    break;
+                }
+                let connection = match connection.upgrade() {
+                    Some(connection) => connection,
+                    None => break,
+                };
+                let status = connection.status();
+                // While recovering, the next handshake starts over with its own credentials
+                if !status.connected() {
+                    break;
+                }
+                let res = match provider.credentials().await {
+                    Ok(credentials) => connection
+                        .update_secret(credentials.credentials.password(), "credentials refresh")
+                        .await
+                        .map(|()| credentials.expires_in),
+                    Err(error) => Err(error),
+                };
+                match res {
+                    Ok(Some(next)) => {
+                        debug!(expires_in=?next, "Refreshed the connection secret");
+                        expires_in = next;
+                        delay = refresh_delay(expires_in);
+                    }
+                    Ok(None) => break,
+                    Err(error) => {
+                        warn!(%error, "Failed to refresh the connection secret");
+                        connection.report_error(error);
+                        // Retry while the current secret is still valid
+                        expires_in = expires_in
+                            .checked_sub(delay)
+                            .unwrap_or_else(|| Duration::from_secs(0));
+                        delay = (expires_in / 2).max(Duration::from_secs(1));
+                    }
+                }
+            }
+        }));
+    }
+}
+
+// Refresh once 80% of the lifetime of the secret has elapsed
+fn refresh_delay(expires_in: Duration) -> Duration {
+    (expires_in * 4 / 5).max(Duration::from_secs(1))
+}
@@ -0,0 +1,187 @@
+use crate::{
+    connection::connect_stream, tcp::HandshakeResult, uri::AMQPUri, ConnectionProperties, Result,
+    TcpStream,
+};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash, Hasher},
+    io,
+    time::Duration,
+};
+use tracing::warn;
+
+/// How to pick the endpoint to connect to when several of them are available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EndpointSelection {
+    /// Always try the endpoints in the order they were given
+    Ordered,
+    /// Try the endpoints in a random order
+    Shuffled,
+    /// Try the endpoints in order, starting with the one following the last one we used
+    RoundRobin,
+}
+
+impl Default for EndpointSelection {
+    fn default() -> Self {
+        Self::Ordered
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Endpoints {
+    uris: Vec<AMQPUri>,
+    selection: EndpointSelection,
+    tls_handshake_timeout: Option<Duration>,
+    current: Option<usize>,
+}
+
+impl Endpoints {
+    pub(crate) fn new(mut uris: Vec<AMQPUri>, options: &ConnectionProperties) -> Self {
+        if let Some(timeout) = options.connection_timeout {
+            for uri in uris.iter_mut() {
+                // An explicit timeout in the URI takes precedence
+                if uri.query.connection_timeout.is_none() {
+                    uri.query.connection_timeout = Some(timeout.as_millis() as u64);
+                }
+            }
+        }
+        Self {
+            uris,
+            selection: options.endpoint_selection,
+            tls_handshake_timeout: options.tls_handshake_timeout,
+            current: None,
+        }
+    }
+
+    fn candidates(&self) -> Vec<usize> {
+        let len = self.uris.len();
+        match self.selection {
+            EndpointSelection::Ordered => (0..len).collect(),
+            EndpointSelection::Shuffled => {
+                let state = RandomState::new();
+                let mut candidates = (0..len).collect::<Vec<_>>();
+                candidates.sort_by_cached_key(|index| {
+                    let mut hasher = state.build_hasher();
+                    index.hash(&mut hasher);
+                    hasher.finish()
+                });
+                candidates
+            }
+            EndpointSelection::RoundRobin => {
+                let start = self.current.map(|current| current + 1).unwrap_or(0);
+                (0..len).map(|index| (start + index) % len).collect()
+            }
+        }
+    }
+
+    /// Try to connect to each endpoint in turn, returning the first one that succeeds
+    pub(crate) fn connect(
+        &mut self,
+        connect: &(dyn Fn(&AMQPUri) -> HandshakeResult + Send + Sync),
+    ) -> Result<(TcpStream, AMQPUri)> {
+        let mut last_error = None;
+        for index in self.candidates() {
+            let uri = &self.uris[index];
+            match connect_stream(connect, uri, self.tls_handshake_timeout) {
+                Ok(stream) => {
+                    self.current = Some(index);
+                    return Ok((stream, uri.clone()));
+                }
+                Err(error) => {
+                    warn!(host=%uri.authority.host, port=%uri.authority.port, %error, "Failed to connect to endpoint");
+                    last_error = Some(error);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no endpoint to connect to").into()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp::{AMQPUriTcpExt, HandshakeError};
+    use std::sync::Mutex;
+
+    fn endpoints(ports: &[u16], selection: EndpointSelection) -> Endpoints {
+        let uris = ports
+            .iter()
+            .map(|port| format!("amqp://127.0.0.1:{}", port).parse().unwrap())
+            .collect();
+        Endpoints::new(
+            uris,
+            &ConnectionProperties::default().with_endpoint_selection(selection),
+        )
+    }
+
+    #[test]
+    fn ordered() {
+        let mut endpoints = endpoints(&[1, 2, 3], EndpointSelection::Ordered);
+        assert_eq!(endpoints.candidates(), vec![0, 1, 2]);
+        endpoints.current = Some(1);
+        assert_eq!(endpoints.candidates(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn round_robin() {
+        let mut endpoints = endpoints(&[1, 2, 3], EndpointSelection::RoundRobin);
+        assert_eq!(endpoints.candidates(), vec![0, 1, 2]);
+        endpoints.current = Some(0);
+        assert_eq!(endpoints.candidates(), vec![1, 2, 0]);
+        endpoints.current = Some(2);
+        assert_eq!(endpoints.candidates(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn shuffled() {
+        let endpoints = endpoints(&[1, 2, 3, 4, 5], EndpointSelection::Shuffled);
+        for _ in 0..10 {
+            let mut candidates = endpoints.candidates();
+            candidates.sort_unstable();
+            assert_eq!(candidates, vec![0, 1, 2, 3, 4]);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::result_large_err)]
+    fn connect_falls_through() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut endpoints = endpoints(&[1, port], EndpointSelection::Ordered);
+        let tried = Mutex::new(Vec::new());
+        let connect = |uri: &AMQPUri| {
+            tried.lock().unwrap().push(uri.authority.port);
+            if uri.authority.port == port {
+                uri.connect()
+            } else {
+                Err(HandshakeError::Failure(
+                    io::ErrorKind::ConnectionRefused.into(),
+                ))
+            }
+        };
+
+        let (_stream, uri) = endpoints.connect(&connect).unwrap();
+        assert_eq!(uri.authority.port, port);
+        assert_eq!(endpoints.current, Some(1));
+        assert_eq!(*tried.lock().unwrap(), vec![1, port]);
+    }
+
+    #[test]
+    #[allow(clippy::result_large_err)]
+    fn connect_fails_when_all_endpoints_fail() {
+        let mut endpoints = endpoints(&[1, 2], EndpointSelection::Ordered);
+        let connect = |_: &AMQPUri| -> HandshakeResult {
+            Err(HandshakeError::Failure(
+                io::ErrorKind::ConnectionRefused.into(),
+            ))
+        };
+
+        assert!(matches!(
+            endpoints.connect(&connect),
+            Err(crate::Error::IOError(_))
+        ));
+        assert_eq!(endpoints.current, None);
+    }
+}
@@ -1,9 +1,12 @@
 use crate::{
-    channel_status::ChannelState, connection_status::ConnectionState, protocol::AMQPError,
-    types::ChannelId,
+    channel_status::ChannelState,
+    connection_status::ConnectionState,
+    consumer_status::ConsumerState,
+    protocol::AMQPError,
+    types::{ChannelId, ShortString},
 };
 use amq_protocol::frame::{GenError, ParserError, ProtocolVersion};
-use std::{error, fmt, io, sync::Arc};
+use std::{error, fmt, io, sync::Arc, time::Duration};
 
 /// A std Result with a lapin::Error error type
 pub type Result<T> = std::result::Result<T, Error>;
@@ -21,13 +24,50 @@ pub enum Error {
     InvalidChannel(ChannelId),
     InvalidChannelState(ChannelState),
     InvalidConnectionState(ConnectionState),
+    InvalidConsumerState(ConsumerState),
+
+    ConnectionTimeout(ConnectionPhase),
+    RpcTimeout(Duration),
+    ConnectionBlocked(String),
+    ChannelFlowPaused(ChannelId),
+    ConsumerBufferFull(ShortString),
+    ConsumerCancelledByServer(ShortString),
+    ChannelInConfirmMode(ChannelId),
+    NoSupportedSaslMechanism(Vec<String>),
+    NoSupportedLocale(Vec<String>),
 
     IOError(Arc<io::Error>),
+    MissingHeartbeatError,
     ParsingError(ParserError),
     ProtocolError(AMQPError),
+    SaslError(String),
+    CodecError(String),
     SerialisationError(Arc<GenError>),
 }
 
+/// The step of the connection process during which an [`Error::ConnectionTimeout`] occurred
+///
+/// [`Error::ConnectionTimeout`]: ./enum.Error.html#variant.ConnectionTimeout
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionPhase {
+    /// Establishing the TCP connection
+    TcpConnect,
+    /// Negotiating TLS
+    TlsHandshake,
+    /// Negotiating the AMQP connection, up to connection.open-ok
+    AmqpHandshake,
+}
+
+impl fmt::Display for ConnectionPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionPhase::TcpConnect => f.write_str("TCP connect"),
+            ConnectionPhase::TlsHandshake => f.write_str("TLS handshake"),
+            ConnectionPhase::AmqpHandshake => f.write_str("AMQP handshake"),
+        }
+    }
+}
+
 impl Error {
     pub fn wouldblock(&self) -> bool {
         if let Error::IOError(e) = self {
@@ -62,10 +102,48 @@ impl fmt::Display for Error {
             Error::InvalidConnectionState(state) => {
                 write!(f, "invalid connection state: {:?}", state)
             }
+            Error::InvalidConsumerState(state) => write!(f, "invalid consumer state: {:?}", state),
+
+            Error::ConnectionTimeout(phase) => write!(f, "connection timed out during {}", phase),
+            Error::RpcTimeout(timeout) => write!(f, "no RPC reply received within {:?}", timeout),
+            Error::ConnectionBlocked(reason) => {
+                write!(f, "the connection is blocked by the server: {}", reason)
+            }
+            Error::ChannelFlowPaused(channel) => {
+                write!(f, "the server paused the flow of channel {}", channel)
+            }
+            Error::ConsumerBufferFull(consumer_tag) => write!(
+                f,
+                "the buffer of consumer {} is full, the delivery got requeued",
+                consumer_tag
+            ),
+            Error::ConsumerCancelledByServer(consumer_tag) => {
+                write!(f, "consumer {} got cancelled by the server", consumer_tag)
+            }
+            Error::ChannelInConfirmMode(channel) => write!(
+                f,
+                "channel {} is in confirm mode, it can't use transactions",
+                channel
+            ),
+            Error::NoSupportedSaslMechanism(offered) => write!(
+                f,
+                "none of our SASL mechanisms is supported by the server, which offers: {}",
+                offered.join(", ")
+            ),
+            Error::NoSupportedLocale(offered) => write!(
+                f,
+                "none of our locales is supported by the server, which offers: {}",
+                offered.join(", ")
+            ),
 
             Error::IOError(e) => write!(f, "IO error: {}", e),
+            Error::MissingHeartbeatError => {
+                write!(f, "no heartbeat received from the server in time")
+            }
             Error::ParsingError(e) => write!(f, "failed to parse: {}", e),
             Error::ProtocolError(e) => write!(f, "protocol error: {}", e),
+            Error::SaslError(e) => write!(f, "SASL authentication failed: {}", e),
+            Error::CodecError(e) => write!(f, "failed to encode or decode the payload: {}", e),
             Error::SerialisationError(e) => write!(f, "failed to serialise: {}", e),
         }
     }
@@ -107,13 +185,45 @@ impl PartialEq for Error {
             (InvalidConnectionState(left_inner), InvalidConnectionState(right_inner)) => {
                 left_inner == right_inner
             }
+            (InvalidConsumerState(left_inner), InvalidConsumerState(right_inner)) => {
+                left_inner == right_inner
+            }
+
+            (ConnectionTimeout(left_inner), ConnectionTimeout(right_inner)) => {
+                left_inner == right_inner
+            }
+            (RpcTimeout(left_inner), RpcTimeout(right_inner)) => left_inner == right_inner,
+            (ConnectionBlocked(left_inner), ConnectionBlocked(right_inner)) => {
+                left_inner == right_inner
+            }
+            (ChannelFlowPaused(left_inner), ChannelFlowPaused(right_inner)) => {
+                left_inner == right_inner
+            }
+            (ConsumerBufferFull(left_inner), ConsumerBufferFull(right_inner)) => {
+                left_inner == right_inner
+            }
+            (ConsumerCancelledByServer(left_inner), ConsumerCancelledByServer(right_inner)) => {
+                left_inner == right_inner
+            }
+            (ChannelInConfirmMode(left_inner), ChannelInConfirmMode(right_inner)) => {
+                left_inner == right_inner
+            }
+            (NoSupportedSaslMechanism(left_inner), NoSupportedSaslMechanism(right_inner)) => {
+                left_inner == right_inner
+            }
+            (NoSupportedLocale(left_inner), NoSupportedLocale(right_inner)) => {
+                left_inner == right_inner
+            }
 
             (IOError(_), IOError(_)) => {
                 error!("Unable to compare lapin::Error::IOError");
                 false
             }
+            (MissingHeartbeatError, MissingHeartbeatError) => true,
             (ParsingError(left_inner), ParsingError(right_inner)) => left_inner == right_inner,
             (ProtocolError(left_inner), ProtocolError(right_inner)) => left_inner == right_inner,
+            (SaslError(left_inner), SaslError(right_inner)) => left_inner == right_inner,
+            (CodecError(left_inner), CodecError(right_inner)) => left_inner == right_inner,
             (SerialisationError(_), SerialisationError(_)) => {
                 error!("Unable to compare lapin::Error::SerialisationError");
                 false
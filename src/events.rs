@@ -0,0 +1,86 @@
+use crate::{
+    types::{ChannelId, ReplyCode},
+    Error,
+};
+use flume::{r#async::RecvStream, Sender};
+use futures_lite::Stream;
+use parking_lot::Mutex;
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// A lifecycle event of a [`Connection`] or of one of its [`Channel`]s.
+///
+/// Subscribe to them using [`Connection::events`].
+///
+/// [`Connection`]: ./struct.Connection.html
+/// [`Channel`]: ./struct.Channel.html
+/// [`Connection::events`]: ./struct.Connection.html#method.events
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    /// We started the handshake with the server
+    Connecting,
+    /// The handshake is over, the connection is usable
+    Connected,
+    /// We lost the connection and are trying to recover it
+    Reconnecting(Error),
+    /// The server blocked the connection, with the given reason
+    Blocked(String),
+    /// The server unblocked the connection
+    Unblocked,
+    /// A channel got opened
+    ChannelOpened(ChannelId),
+    /// A channel got closed, either by us or by the server
+    ChannelClosed {
+        channel_id: ChannelId,
+        reply_code: ReplyCode,
+        reply_text: String,
+    },
+    /// The server cancelled a consumer, e.g. because its queue got deleted
+    ConsumerCancelled {
+        channel_id: ChannelId,
+        consumer_tag: String,
+    },
+    /// The connection got closed, either by us or by the server
+    ConnectionClosed {
+        reply_code: ReplyCode,
+        reply_text: String,
+    },
+    /// The connection failed
+    ConnectionError(Error),
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Events(Arc<Mutex<Vec<Sender<Event>>>>);
+
+impl Events {
+    pub(crate) fn subscribe(&self) -> EventStream {
+        let (sender, receiver) = flume::unbounded();
+        self.0.lock().push(sender);
+        EventStream(receiver.into_stream())
+    }
+
+    pub(crate) fn emit(&self, event: Event) {
+        // Forget about the subscribers which dropped their stream
+        self.0
+            .lock()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+/// A Stream of the lifecycle [`Event`]s of a [`Connection`]
+///
+/// [`Event`]: ./enum.Event.html
+/// [`Connection`]: ./struct.Connection.html
+pub struct EventStream(RecvStream<'static, Event>);
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
@@ -245,6 +245,7 @@ pub(crate) enum Reply {
         BasicConsumeOptions,
         FieldTable,
         Option<Consumer>,
+        bool,
     ),
     BasicCancelOk(PromiseResolver<()>),
     BasicGetOk(
@@ -393,7 +394,7 @@ impl Channel {
         locale: &str,
         resolver: PromiseResolver<Connection>,
         connection: Connection,
-        credentials: Credentials,
+        sasl: Box<dyn SaslSession>,
     ) -> Result<()> {
         if !self.status.connected() {
             return Err(Error::InvalidChannelState(self.status.state()));
@@ -413,7 +414,7 @@ impl Channel {
             promise.set_marker("connection.start-ok".into());
         }
         self.send_method_frame(method, send_resolver, None);
-        self.on_connection_start_ok_sent(resolver, connection, credentials);
+        self.on_connection_start_ok_sent(resolver, connection, sasl);
         promise.await
     }
     fn receive_connection_secure(&self, method: protocol::connection::Secure) -> Result<()> {
@@ -847,7 +848,7 @@ impl Channel {
             return Err(Error::InvalidChannelState(self.status.state()));
         }
 
-        self.before_channel_close();
+        self.before_channel_close(reply_code, reply_text);
         let method = AMQPClass::Channel(protocol::channel::AMQPMethod::Close(
             protocol::channel::Close {
                 reply_code,
@@ -1703,6 +1704,7 @@ impl Channel {
         options: BasicConsumeOptions,
         arguments: FieldTable,
         original: Option<Consumer>,
+        streaming: bool,
     ) -> Result<Consumer> {
         if !self.status.connected() {
             return Err(Error::InvalidChannelState(self.status.state()));
@@ -1746,6 +1748,7 @@ impl Channel {
                     options,
                     creation_arguments,
                     original,
+                    streaming,
                 ),
                 Box::new(resolver),
             )),
@@ -1771,6 +1774,7 @@ impl Channel {
                 options,
                 creation_arguments,
                 original,
+                streaming,
             )) => self.on_basic_consume_ok_received(
                 method,
                 resolver,
@@ -1779,6 +1783,7 @@ impl Channel {
                 options,
                 creation_arguments,
                 original,
+                streaming,
             ),
             _ => self.handle_invalid_contents(
                 format!(
@@ -1874,12 +1879,12 @@ impl Channel {
             ),
         }
     }
-    pub async fn basic_publish(
+    async fn do_basic_publish(
         &self,
         exchange: &str,
         routing_key: &str,
         options: BasicPublishOptions,
-        payload: Vec<u8>,
+        payload: impl Into<Bytes>,
         properties: BasicProperties,
     ) -> Result<PublisherConfirm> {
         if !self.status.connected() {
@@ -1900,7 +1905,7 @@ impl Channel {
             },
         ));
 
-        self.send_method_frame_with_body(method, payload, properties, start_hook_res)
+        self.send_method_frame_with_body(method, payload.into(), properties, start_hook_res)
             .await
     }
     fn receive_basic_return(&self, method: protocol::basic::Return) -> Result<()> {
@@ -2024,6 +2029,7 @@ impl Channel {
             promise.set_marker("basic.reject".into());
         }
         self.send_method_frame(method, send_resolver, None);
+        self.on_basic_reject_sent(delivery_tag);
         promise.await
     }
     pub async fn basic_recover_async(&self, options: BasicRecoverAsyncOptions) -> Result<()> {
@@ -2157,7 +2163,7 @@ impl Channel {
 
         match self.frames.next_expected_reply(self.id) {
             Some(Reply::TxSelectOk(resolver)) => {
-                let res = Ok(());
+                let res = self.on_tx_select_ok_received();
                 resolver.swear(res.clone());
                 res
             }
@@ -1,4 +1,4 @@
-use crate::channels::Channels;
+use crate::{channels::Channels, socket_state::SocketStateHandle};
 use executor_trait::FullExecutor;
 use parking_lot::Mutex;
 use reactor_trait::Reactor;
@@ -13,6 +13,7 @@ pub struct Heartbeat {
     channels: Channels,
     executor: Arc<dyn FullExecutor + Send + Sync>,
     reactor: Arc<dyn Reactor + Send + Sync>,
+    waker: SocketStateHandle,
     inner: Arc<Mutex<Inner>>,
 }
 
@@ -21,18 +22,22 @@ impl Heartbeat {
         channels: Channels,
         executor: Arc<dyn FullExecutor + Send + Sync>,
         reactor: Arc<dyn Reactor + Send + Sync>,
+        waker: SocketStateHandle,
     ) -> Self {
         let inner = Default::default();
         Self {
             channels,
             executor,
             reactor,
+            waker,
             inner,
         }
     }
 
     pub(crate) fn set_timeout(&self, timeout: Duration) {
-        self.inner.lock().timeout = Some(timeout);
+        let mut inner = self.inner.lock();
+        inner.timeout = Some(timeout);
+        inner.update_last_read();
     }
 
     pub(crate) fn start(&self) {
@@ -45,13 +50,22 @@ impl Heartbeat {
     }
 
     fn poll_timeout(&self) -> Option<Duration> {
-        self.inner.lock().poll_timeout(&self.channels)
+        self.inner.lock().poll_timeout(&self.channels, &self.waker)
     }
 
     pub(crate) fn update_last_write(&self) {
         self.inner.lock().update_last_write();
     }
 
+    pub(crate) fn update_last_read(&self) {
+        self.inner.lock().update_last_read();
+    }
+
+    /// Whether the server stayed silent for more than twice the negotiated heartbeat interval
+    pub(crate) fn missed_heartbeats(&self) -> bool {
+        self.inner.lock().missed_heartbeats()
+    }
+
     pub(crate) fn cancel(&self) {
         self.inner.lock().timeout = None;
     }
@@ -65,6 +79,7 @@ impl fmt::Debug for Heartbeat {
 
 struct Inner {
     last_write: Instant,
+    last_read: Instant,
     timeout: Option<Duration>,
 }
 
@@ -72,15 +87,16 @@ impl Default for Inner {
     fn default() -> Self {
         Self {
             last_write: Instant::now(),
+            last_read: Instant::now(),
             timeout: None,
         }
     }
 }
 
 impl Inner {
-    fn poll_timeout(&mut self, channels: &Channels) -> Option<Duration> {
+    fn poll_timeout(&mut self, channels: &Channels, waker: &SocketStateHandle) -> Option<Duration> {
         self.timeout.map(|timeout| {
-            timeout
+            let write_timeout = timeout
                 .checked_sub(self.last_write.elapsed())
                 .map(|timeout| timeout.max(Duration::from_millis(1)))
                 .unwrap_or_else(|| {
@@ -88,11 +104,35 @@ impl Inner {
                     self.update_last_write();
                     channels.send_heartbeat();
                     timeout
-                })
+                });
+            let read_timeout = server_timeout(timeout)
+                .checked_sub(self.last_read.elapsed())
+                .map(|timeout| timeout.max(Duration::from_millis(1)))
+                .unwrap_or_else(|| {
+                    // Wake the io loop up so that it notices the server is gone
+                    waker.wake();
+                    timeout
+                });
+            write_timeout.min(read_timeout)
+        })
+    }
+
+    fn missed_heartbeats(&self) -> bool {
+        self.timeout.map_or(false, |timeout| {
+            self.last_read.elapsed() > server_timeout(timeout)
         })
     }
 
     fn update_last_write(&mut self) {
         self.last_write = Instant::now();
     }
+
+    fn update_last_read(&mut self) {
+        self.last_read = Instant::now();
+    }
+}
+
+// We send heartbeats every half negotiated interval, give up after two full intervals of silence
+fn server_timeout(timeout: Duration) -> Duration {
+    timeout * 4
 }
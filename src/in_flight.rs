@@ -0,0 +1,98 @@
+use flume::{Receiver, Sender};
+use std::fmt;
+
+/// Limits how many operations can be ongoing at the same time
+#[derive(Clone)]
+pub(crate) struct InFlight {
+    max: usize,
+    slots: Sender<()>,
+    in_flight: Receiver<()>,
+}
+
+impl InFlight {
+    pub(crate) fn new(max: usize) -> Self {
+        let max = max.max(1);
+        let (slots, in_flight) = flume::bounded(max);
+        Self {
+            max,
+            slots,
+            in_flight,
+        }
+    }
+
+    /// How many operations are currently ongoing
+    pub(crate) fn len(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Wait for a slot, which gets freed once the returned permit gets dropped
+    pub(crate) async fn acquire(&self) -> Permit {
+        // This can't fail as we hold a Receiver
+        let _ = self.slots.send_async(()).await;
+        Permit(self.in_flight.clone())
+    }
+
+    /// Wait for all the ongoing operations to complete
+    pub(crate) async fn wait_idle(&self) {
+        let mut permits = Vec::with_capacity(self.max);
+        for _ in 0..self.max {
+            permits.push(self.acquire().await);
+        }
+    }
+}
+
+impl fmt::Debug for InFlight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InFlight")
+            .field("max", &self.max)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// A slot taken in an `InFlight`, freed when dropped
+pub(crate) struct Permit(Receiver<()>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        // Free our slot
+        let _ = self.0.try_recv();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures_lite::future;
+
+    #[test]
+    fn permits() {
+        let in_flight = InFlight::new(2);
+        let first = future::block_on(in_flight.acquire());
+        let second = future::block_on(in_flight.acquire());
+        assert_eq!(in_flight.len(), 2);
+        assert!(future::block_on(future::poll_once(in_flight.acquire())).is_none());
+
+        drop(first);
+        assert_eq!(in_flight.len(), 1);
+        drop(second);
+        assert_eq!(in_flight.len(), 0);
+        assert!(future::block_on(future::poll_once(in_flight.wait_idle())).is_some());
+    }
+
+    #[test]
+    fn dropped_future_frees_its_slot() {
+        let in_flight = InFlight::new(1);
+        {
+            // Like a publish being dropped while waiting for the server
+            let mut publish = Box::pin(async {
+                let _permit = in_flight.acquire().await;
+                future::pending::<()>().await;
+            });
+            assert!(future::block_on(future::poll_once(&mut publish)).is_none());
+            assert_eq!(in_flight.len(), 1);
+        }
+        assert_eq!(in_flight.len(), 0);
+    }
+}
@@ -2,7 +2,10 @@ use crate::{
     channels::Channels,
     consumer_status::ConsumerStatus,
     error_holder::ErrorHolder,
-    options::{BasicAckOptions, BasicCancelOptions, BasicNackOptions, BasicRejectOptions},
+    options::{
+        BasicAckOptions, BasicCancelOptions, BasicNackOptions, BasicRejectOptions,
+        ChannelFlowOptions,
+    },
     socket_state::SocketStateHandle,
     types::{ChannelId, DeliveryTag, Identifier, ReplyCode},
     Error, PromiseResolver, Result,
@@ -89,6 +92,10 @@ impl InternalRPCHandle {
         ));
     }
 
+    pub(crate) fn set_channel_flow(&self, channel_id: ChannelId, active: bool) {
+        self.send(InternalCommand::SetChannelFlow(channel_id, active));
+    }
+
     pub(crate) fn close_channel(
         &self,
         channel_id: ChannelId,
@@ -176,7 +183,7 @@ impl fmt::Debug for InternalRPCHandle {
 }
 
 #[derive(Debug)]
-enum InternalCommand {
+pub(crate) enum InternalCommand {
     BasicAck(
         ChannelId,
         DeliveryTag,
@@ -199,6 +206,7 @@ enum InternalCommand {
         Option<ErrorHolder>,
     ),
     CancelConsumer(ChannelId, String, ConsumerStatus),
+    SetChannelFlow(ChannelId, bool),
     CloseChannel(ChannelId, ReplyCode, String),
     CloseConnection(ReplyCode, String, Identifier, Identifier),
     SendConnectionCloseOk(Error),
@@ -226,6 +234,11 @@ impl InternalRPC {
         self.handle.clone()
     }
 
+    #[cfg(test)]
+    pub(crate) fn try_recv(&self) -> Option<InternalCommand> {
+        self.rpc.try_recv().ok().flatten()
+    }
+
     pub(crate) async fn run(self, channels: Channels) {
         use InternalCommand::*;
 
@@ -242,7 +255,7 @@ impl InternalRPC {
                             if let Some(error) = error {
                                 error.check()?;
                             }
-                            channel?.basic_ack(delivery_tag, options).await
+                            channel?.ack_delivery(delivery_tag, options).await
                         },
                         resolver,
                     )
@@ -254,7 +267,7 @@ impl InternalRPC {
                             if let Some(error) = error {
                                 error.check()?;
                             }
-                            channel?.basic_nack(delivery_tag, options).await
+                            channel?.nack_delivery(delivery_tag, options).await
                         },
                         resolver,
                     )
@@ -284,6 +297,20 @@ impl InternalRPC {
                         }
                     })
                 }
+                SetChannelFlow(channel_id, active) => {
+                    let channel = get_channel(channel_id);
+                    handle.register_internal_future(async move {
+                        let channel = channel?;
+                        if channel.status().connected() {
+                            channel
+                                .channel_flow(ChannelFlowOptions { active })
+                                .await
+                                .map(|_| ())
+                        } else {
+                            Ok(())
+                        }
+                    })
+                }
                 CloseChannel(channel_id, reply_code, reply_text) => {
                     let channel = get_channel(channel_id);
                     handle.register_internal_future(async move {
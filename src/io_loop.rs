@@ -6,6 +6,7 @@ use crate::{
     heartbeat::Heartbeat,
     internal_rpc::InternalRPCHandle,
     protocol::{self, AMQPError, AMQPHardError},
+    recovery::Recovery,
     socket_state::{SocketEvent, SocketState},
     thread::ThreadHandle,
     types::FrameSize,
@@ -30,6 +31,7 @@ const FRAMES_STORAGE: usize = 32;
 enum Status {
     Initial,
     Connected,
+    Recovering,
     Stop,
 }
 
@@ -48,9 +50,11 @@ pub struct IoLoop {
     receive_buffer: Buffer,
     send_buffer: Buffer,
     serialized_frames: VecDeque<(FrameSize, Option<PromiseResolver<()>>)>,
+    recovery: Option<Recovery>,
 }
 
 impl IoLoop {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) async fn new(
         connection_status: ConnectionStatus,
         configuration: Configuration,
@@ -61,6 +65,7 @@ impl IoLoop {
         connection_io_loop_handle: ThreadHandle,
         stream: Pin<Box<dyn AsyncIOHandle + Send>>,
         heartbeat: Heartbeat,
+        recovery: Option<Recovery>,
     ) -> Result<Self> {
         let frame_size = std::cmp::max(
             protocol::constants::FRAME_MIN_SIZE,
@@ -82,6 +87,7 @@ impl IoLoop {
             receive_buffer: Buffer::with_capacity(FRAMES_STORAGE * frame_size as usize),
             send_buffer: Buffer::with_capacity(FRAMES_STORAGE * frame_size as usize),
             serialized_frames: VecDeque::default(),
+            recovery,
         })
     }
 
@@ -109,6 +115,9 @@ impl IoLoop {
                 self.heartbeat.set_timeout(heartbeat);
                 self.heartbeat.start();
             }
+            if let Some(recovery) = self.recovery.as_mut() {
+                recovery.finish();
+            }
             self.status = Status::Connected;
         }
         Ok(true)
@@ -118,6 +127,7 @@ impl IoLoop {
         match self.status {
             Status::Initial => self.finish_setup(),
             Status::Connected => Ok(true),
+            Status::Recovering => self.recover(),
             Status::Stop => Ok(false),
         }
     }
@@ -133,7 +143,9 @@ impl IoLoop {
     }
 
     fn can_read(&mut self) -> bool {
-        self.socket_state.readable() && self.receive_buffer.available_space() > 0
+        self.socket_state.readable()
+            && self.receive_buffer.available_space() > 0
+            && !self.configuration.buffer_usage().reading_paused()
     }
 
     fn can_parse(&self) -> bool {
@@ -200,6 +212,18 @@ impl IoLoop {
         if !self.ensure_setup()? {
             return Ok(());
         }
+        if self.status == Status::Initial {
+            if let Some(recovery) = self.recovery.as_ref() {
+                recovery.check_handshake()?;
+            }
+        }
+        // We can't tell whether the server is still alive while we're not reading
+        if self.status == Status::Connected
+            && !self.configuration.buffer_usage().reading_paused()
+            && self.heartbeat.missed_heartbeats()
+        {
+            return Err(Error::MissingHeartbeatError);
+        }
         self.check_connection_state();
         trace!(
             can_read=%self.socket_state.readable(),
@@ -230,6 +254,19 @@ impl IoLoop {
     }
 
     fn critical_error(&mut self, error: Error) -> Result<()> {
+        if self.status == Status::Recovering {
+            // We already handled this error when starting the recovery
+            return Ok(());
+        }
+        let connected = self.status == Status::Connected;
+        if let Some(recovery) = self.recovery.as_mut() {
+            if recovery.can_recover(&error, connected) {
+                recovery.start(error.clone());
+                self.start_recovery(error);
+                // Keep the io loop running so that it reconnects
+                return Ok(());
+            }
+        }
         if let Some(resolver) = self.connection_status.connection_resolver() {
             resolver.swear(Err(error.clone()));
         }
@@ -243,17 +280,65 @@ impl IoLoop {
         Err(error)
     }
 
+    fn start_recovery(&mut self, error: Error) {
+        if let Some(resolver) = self.connection_status.connection_resolver() {
+            resolver.swear(Err(error.clone()));
+        }
+        self.status = Status::Recovering;
+        self.heartbeat.cancel();
+        self.channels.set_connection_recovering(error.clone());
+        for (_, resolver) in std::mem::take(&mut self.serialized_frames) {
+            if let Some(resolver) = resolver {
+                resolver.swear(Err(error.clone()));
+            }
+        }
+        self.receive_buffer
+            .consume(self.receive_buffer.available_data());
+        self.send_buffer.consume(self.send_buffer.available_data());
+    }
+
+    fn recover(&mut self) -> Result<bool> {
+        let res = match self.recovery.take() {
+            Some(mut recovery) => {
+                let res = self.reconnect(&mut recovery);
+                self.recovery = Some(recovery);
+                res
+            }
+            None => Err(Error::InvalidConnectionState(ConnectionState::Reconnecting)),
+        };
+        match res {
+            Ok(()) => self.status = Status::Initial,
+            Err(error) => {
+                self.status = Status::Stop;
+                if self.connection_status.reconnecting() {
+                    self.channels.set_connection_error(error);
+                } else {
+                    self.channels.set_connection_closed(error);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn reconnect(&mut self, recovery: &mut Recovery) -> Result<()> {
+        self.stream = recovery.reconnect(&mut self.socket_state, &self.connection_status)?;
+        self.socket_state.reset();
+        // Drop anything that could have been queued while we were reconnecting
+        self.frames
+            .drop_pending(Error::InvalidConnectionState(ConnectionState::Reconnecting));
+        recovery.start_handshake(self.socket_state.handle())
+    }
+
     fn attempt_flush(&mut self, noop_context: &mut Context<'_>) -> Result<()> {
         let res = self.flush(noop_context);
         self.handle_io_result(res)
     }
 
     fn handle_io_result(&mut self, result: Result<()>) -> Result<()> {
-        if let Err(e) = self.socket_state.handle_io_result(result) {
+        self.socket_state.handle_io_result(result).map_err(|e| {
             error!(error=?e, "error doing IO");
-            self.critical_error(e)?;
-        }
-        Ok(())
+            e
+        })
     }
 
     fn flush(&mut self, writable_context: &mut Context<'_>) -> Result<()> {
@@ -341,6 +426,8 @@ impl IoLoop {
 
                 if let Some(sz) = self.socket_state.handle_read_poll(res) {
                     if sz > 0 {
+                        self.heartbeat.update_last_read();
+
                         trace!("read {} bytes", sz);
                         self.receive_buffer.fill(sz);
                     } else {
@@ -375,7 +462,7 @@ impl IoLoop {
                         }
                         e => {
                             error!(error=?e, "error generating frame");
-                            self.critical_error(Error::SerialisationError(Arc::new(e)))?;
+                            return Err(Error::SerialisationError(Arc::new(e)));
                         }
                     }
                 }
@@ -412,7 +499,7 @@ impl IoLoop {
                         0,
                         0,
                     );
-                    self.critical_error(Error::ProtocolError(error))?;
+                    return Err(Error::ProtocolError(error));
                 }
                 self.receive_buffer.consume(consumed);
                 Ok(Some(f))
@@ -420,7 +507,7 @@ impl IoLoop {
             Err(e) => {
                 if !e.is_incomplete() {
                     error!(error=?e, "parse error");
-                    self.critical_error(Error::ParsingError(e))?;
+                    return Err(Error::ParsingError(e));
                 }
                 Ok(None)
             }
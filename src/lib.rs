@@ -10,7 +10,10 @@
 //!
 //! ## Feature switches
 //!
+//! * `cbor`: enable the CBOR payload codec for typed publishing and consuming
 //! * `codegen`: generate code instead of using pregenerated one
+//! * `json`: enable the JSON payload codec for typed publishing and consuming
+//! * `msgpack`: enable the MessagePack payload codec for typed publishing and consuming
 //! * `native-tls` (*default*): enable amqps support through native-tls
 //! * `openssl`: enable amqps support through openssl (preferred over native-tls when set)
 //! * `rustls`: enable amqps support through rustls (preferred over openssl when set, uses rustls-native-certs by default)
@@ -105,17 +108,35 @@ pub use amq_protocol::{
     types, uri,
 };
 
+pub use ack_batcher::AckBatching;
 pub use channel::{options, Channel};
 pub use channel_status::{ChannelState, ChannelStatus};
-pub use configuration::Configuration;
+#[cfg(any(feature = "json", feature = "cbor", feature = "msgpack"))]
+pub use codec::{Codec, TypedConsumer, TypedDelivery};
+pub use configuration::{Configuration, PublishBackpressure};
 pub use connection::{Connect, Connection};
 pub use connection_properties::ConnectionProperties;
-pub use connection_status::{ConnectionState, ConnectionStatus};
+pub use connection_status::{ConnectionState, ConnectionStatus, Unblocked};
 pub use consumer::{Consumer, ConsumerDelegate};
+pub use consumer_buffer::{BufferLimits, BufferOverflowPolicy};
+pub use consumer_runner::{ConsumerRunner, FailurePolicy, Outcome};
 pub use consumer_status::ConsumerState;
-pub use error::{Error, Result};
+pub use credentials_provider::{CredentialsProvider, ExpiringCredentials};
+pub use endpoints::EndpointSelection;
+pub use error::{ConnectionPhase, Error, Result};
+pub use events::{Event, EventStream};
 pub use exchange::ExchangeKind;
 pub use queue::Queue;
+pub use recovery_config::RecoveryConfig;
+pub use reliable_publisher::{PublishHandle, PublishOutcome, ReliablePublisher, RetryPolicy};
+pub use retry_topology::{RetryOutcome, RetryTopology};
+pub use returned_messages::ReturnedMessageStream;
+pub use rpc_client::RpcClient;
+pub use rpc_server::RpcServer;
+pub use sasl::{SaslMechanism, SaslSession};
+pub use server_properties::ServerProperties;
+pub use shutdown::ShutdownReport;
+pub use streaming::{BodyStream, StreamingConsumer, StreamingDelivery};
 
 pub mod heartbeat;
 pub mod message;
@@ -126,8 +147,10 @@ pub mod topology;
 type Promise<T> = pinky_swear::PinkySwear<Result<T>>;
 type PromiseResolver<T> = pinky_swear::Pinky<Result<T>>;
 
+mod ack_batcher;
 mod acker;
 mod acknowledgement;
+mod backoff;
 mod basic_get_delivery;
 mod buffer;
 mod channel;
@@ -135,27 +158,46 @@ mod channel_closer;
 mod channel_receiver_state;
 mod channel_status;
 mod channels;
+#[cfg(any(feature = "json", feature = "cbor", feature = "msgpack"))]
+mod codec;
 mod configuration;
 mod connection;
 mod connection_closer;
 mod connection_properties;
 mod connection_status;
 mod consumer;
+mod consumer_buffer;
 mod consumer_canceler;
+mod consumer_runner;
 mod consumer_status;
 mod consumers;
+mod credentials_provider;
+mod endpoints;
 mod error;
 mod error_handler;
 mod error_holder;
+mod events;
 mod exchange;
 mod frames;
 mod id_sequence;
+mod in_flight;
 mod internal_rpc;
 mod io_loop;
 mod parsing;
 mod queue;
+mod recovery;
+mod recovery_config;
 mod registry;
+mod reliable_publisher;
+mod retry_topology;
 mod returned_messages;
+mod rpc_client;
+mod rpc_server;
+mod sasl;
+mod server_properties;
+mod shutdown;
+mod streaming;
 mod thread;
 mod topology_internal;
+mod transaction;
 mod wakers;
@@ -2,11 +2,13 @@ use crate::{
     acker::Acker,
     error_holder::ErrorHolder,
     internal_rpc::InternalRPCHandle,
+    options::BasicPublishOptions,
     protocol::AMQPError,
     types::ShortString,
-    types::{ChannelId, DeliveryTag, MessageCount, ReplyCode},
+    types::{ChannelId, DeliveryTag, MessageCount, PayloadSize, ReplyCode},
     BasicProperties, Result,
 };
+use bytes::{Bytes, BytesMut};
 use std::ops::{Deref, DerefMut};
 
 /// Type wrapping the output of a consumer
@@ -47,7 +49,10 @@ pub struct Delivery {
     pub properties: BasicProperties,
 
     /// The payload of the message in binary format.
-    pub data: Vec<u8>,
+    ///
+    /// A payload received in a single frame is handed out without being copied, one spanning
+    /// several frames gets copied into a buffer allocated once for the whole payload.
+    pub data: Bytes,
 
     /// The acker used to ack/nack the message
     pub acker: Acker,
@@ -69,13 +74,31 @@ impl Delivery {
             routing_key,
             redelivered,
             properties: BasicProperties::default(),
-            data: Vec::default(),
+            data: Bytes::default(),
             acker: Acker::new(channel_id, delivery_tag, internal_rpc, error),
         }
     }
 
-    pub(crate) fn receive_content(&mut self, data: Vec<u8>) {
-        self.data.extend(data);
+    /// Receive a body frame, using `buffer` to assemble payloads spanning several frames
+    pub(crate) fn receive_content(
+        &mut self,
+        data: Vec<u8>,
+        remaining_size: PayloadSize,
+        buffer: &mut BytesMut,
+    ) {
+        if buffer.is_empty() {
+            if remaining_size == 0 {
+                // The whole payload fits in one frame, no need to copy it
+                self.data = data.into();
+                return;
+            }
+            // Allocate the whole payload at once
+            buffer.reserve(data.len() + remaining_size as usize);
+        }
+        buffer.extend_from_slice(&data);
+        if remaining_size == 0 {
+            self.data = buffer.split().freeze();
+        }
     }
 }
 
@@ -171,3 +194,51 @@ impl DerefMut for BasicReturnMessage {
         &mut self.delivery
     }
 }
+
+/// A message to publish as part of a batch using [`Channel::basic_publish_batch`].
+///
+/// [`Channel::basic_publish_batch`]: ../struct.Channel.html#method.basic_publish_batch
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchMessage {
+    /// The exchange to publish the message to
+    pub exchange: ShortString,
+
+    /// The routing key of the message
+    pub routing_key: ShortString,
+
+    /// The options used to publish the message
+    pub options: BasicPublishOptions,
+
+    /// The payload of the message in binary format.
+    ///
+    /// It gets copied in the same cases as with [`Channel::basic_publish`].
+    ///
+    /// [`Channel::basic_publish`]: ../struct.Channel.html#method.basic_publish
+    pub payload: Bytes,
+
+    /// Contains the properties and the headers of the
+    /// message.
+    pub properties: BasicProperties,
+}
+
+impl BatchMessage {
+    pub fn new(exchange: &str, routing_key: &str, payload: impl Into<Bytes>) -> Self {
+        Self {
+            exchange: exchange.into(),
+            routing_key: routing_key.into(),
+            options: BasicPublishOptions::default(),
+            payload: payload.into(),
+            properties: BasicProperties::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: BasicPublishOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_properties(mut self, properties: BasicProperties) -> Self {
+        self.properties = properties;
+        self
+    }
+}
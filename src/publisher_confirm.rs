@@ -86,3 +86,81 @@ impl Drop for PublisherConfirm {
         }
     }
 }
+
+/// The confirmations of a batch of messages published using [`Channel::basic_publish_batch`]
+///
+/// [`Channel::basic_publish_batch`]: ../struct.Channel.html#method.basic_publish_batch
+pub struct BatchConfirm(Pin<Box<dyn Future<Output = BatchConfirmation> + Send>>);
+
+impl BatchConfirm {
+    pub(crate) fn new(confirms: Vec<PublisherConfirm>) -> Self {
+        Self(Box::pin(async move {
+            let mut confirmations = Vec::with_capacity(confirms.len());
+            for confirm in confirms {
+                // Keep going on errors so that the outcome of the other messages isn't lost
+                confirmations.push(confirm.await);
+            }
+            BatchConfirmation { confirmations }
+        }))
+    }
+}
+
+impl fmt::Debug for BatchConfirm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchConfirm").finish()
+    }
+}
+
+impl Future for BatchConfirm {
+    type Output = BatchConfirmation;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+/// The confirmations received for a batch of messages, in the order they were published
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BatchConfirmation {
+    /// The confirmation of each message of the batch, or the error which prevented it from
+    /// being received
+    pub confirmations: Vec<Result<Confirmation>>,
+}
+
+impl BatchConfirmation {
+    /// How many messages were acked by the server
+    pub fn acks(&self) -> usize {
+        self.confirmed().filter(|c| c.is_ack()).count()
+    }
+
+    /// How many messages were nacked by the server
+    pub fn nacks(&self) -> usize {
+        self.confirmed().filter(|c| c.is_nack()).count()
+    }
+
+    /// How many messages were returned by the server as they couldn't be routed
+    pub fn returns(&self) -> usize {
+        self.confirmed()
+            .filter(|c| matches!(c, Confirmation::Ack(Some(_)) | Confirmation::Nack(Some(_))))
+            .count()
+    }
+
+    /// How many messages didn't get any confirmation because of an error
+    pub fn errors(&self) -> usize {
+        self.confirmations.iter().filter(|c| c.is_err()).count()
+    }
+
+    /// Whether every message was acked without being returned
+    pub fn all_acked(&self) -> bool {
+        self.confirmations.iter().all(|c| {
+            matches!(
+                c,
+                Ok(Confirmation::Ack(None)) | Ok(Confirmation::NotRequested)
+            )
+        })
+    }
+
+    fn confirmed(&self) -> impl Iterator<Item = &Confirmation> {
+        self.confirmations.iter().filter_map(|c| c.as_ref().ok())
+    }
+}
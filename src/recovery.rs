@@ -0,0 +1,184 @@
+use crate::{
+    connection::{wait_handshake, Connector, WeakConnection},
+    credentials_provider::{fetch_credentials, SecretRefresh},
+    endpoints::Endpoints,
+    error_holder::ErrorHolder,
+    socket_state::{SocketState, SocketStateHandle},
+    uri::AMQPUri,
+    ConnectionProperties, ConnectionState, ConnectionStatus, Error, RecoveryConfig, Result,
+};
+use executor_trait::FullExecutor;
+use reactor_trait::{AsyncIOHandle, IOHandle, Reactor};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+pub(crate) struct Recovery {
+    config: RecoveryConfig,
+    endpoints: Endpoints,
+    uri: AMQPUri,
+    connect: Connector,
+    options: ConnectionProperties,
+    connection: WeakConnection,
+    secret_refresh: SecretRefresh,
+    executor: Arc<dyn FullExecutor + Send + Sync>,
+    reactor: Arc<dyn Reactor + Send + Sync>,
+    error: Option<Error>,
+    handshake_error: ErrorHolder,
+}
+
+impl Recovery {
+    pub(crate) fn new(
+        config: RecoveryConfig,
+        endpoints: Endpoints,
+        uri: AMQPUri,
+        connect: Connector,
+        options: ConnectionProperties,
+        connection: WeakConnection,
+        secret_refresh: SecretRefresh,
+        executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn Reactor + Send + Sync>,
+    ) -> Self {
+        Self {
+            config,
+            endpoints,
+            uri,
+            connect,
+            options,
+            connection,
+            secret_refresh,
+            executor,
+            reactor,
+            error: None,
+            handshake_error: ErrorHolder::default(),
+        }
+    }
+
+    /// Whether we should try to recover from this error.
+    ///
+    /// We only recover connections which were fully established at least once, failures during
+    /// the initial handshake are reported to the caller of connect. While recovering, a timeout
+    /// means the new socket isn't usable and we try another one.
+    pub(crate) fn can_recover(&self, error: &Error, connected: bool) -> bool {
+        (connected || self.recovering())
+            && matches!(
+                error,
+                Error::IOError(_) | Error::MissingHeartbeatError | Error::ConnectionTimeout(_)
+            )
+    }
+
+    pub(crate) fn recovering(&self) -> bool {
+        self.error.is_some()
+    }
+
+    pub(crate) fn start(&mut self, error: Error) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    pub(crate) fn finish(&mut self) {
+        self.error = None;
+    }
+
+    pub(crate) fn reconnect(
+        &mut self,
+        socket_state: &mut SocketState,
+        status: &ConnectionStatus,
+    ) -> Result<Pin<Box<dyn AsyncIOHandle + Send>>> {
+        let error = self
+            .error
+            .clone()
+            .unwrap_or(Error::InvalidConnectionState(ConnectionState::Reconnecting));
+        let mut attempt = 0;
+        loop {
+            if !self.config.can_retry(attempt) {
+                error!(attempts=%attempt, %error, "Giving up on connection recovery");
+                return Err(error);
+            }
+
+            // Wait before the next attempt, making sure the connection didn't get closed meanwhile
+            let deadline = Instant::now() + self.config.delay(attempt);
+            loop {
+                if !status.reconnecting() || !self.connection.is_alive() {
+                    return Err(Error::InvalidConnectionState(status.state()));
+                }
+                match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) if timeout > Duration::from_secs(0) => {
+                        socket_state.wait_timeout(timeout)
+                    }
+                    _ => break,
+                }
+            }
+
+            attempt += 1;
+            info!(%attempt, "Trying to reconnect");
+            match self
+                .endpoints
+                .connect(&*self.connect)
+                .and_then(|(stream, uri)| {
+                    let stream = self.reactor.register(IOHandle::new(stream))?.into();
+                    Ok((stream, uri))
+                }) {
+                Ok((stream, uri)) => {
+                    self.uri = uri;
+                    return Ok(stream);
+                }
+                Err(err) => warn!(%attempt, error=%err, "Reconnection attempt failed"),
+            }
+        }
+    }
+
+    /// Whether the handshake on the new socket failed without the io loop noticing
+    pub(crate) fn check_handshake(&self) -> Result<()> {
+        self.handshake_error.check()
+    }
+
+    pub(crate) fn start_handshake(&mut self, waker: SocketStateHandle) -> Result<()> {
+        let connection = self
+            .connection
+            .upgrade()
+            .ok_or(Error::InvalidConnectionState(ConnectionState::Closed))?;
+        let uri = self.uri.clone();
+        let options = self.options.clone();
+        let reactor = self.reactor.clone();
+        // A fresh holder for each attempt, so that a previous handshake can't fail this one
+        self.handshake_error = ErrorHolder::default();
+        let handshake_error = self.handshake_error.clone();
+        let secret_refresh = self.secret_refresh.clone();
+        self.executor.spawn(Box::pin(async move {
+            let res = match fetch_credentials(&uri, &options).await {
+                Ok(credentials) => {
+                    let expires_in = credentials.expires_in;
+                    let timeout = options.amqp_handshake_timeout;
+                    let (promise_out, promise_in) =
+                        connection.start_handshake(&uri, credentials.credentials, options);
+                    let res = wait_handshake(promise_out, promise_in, timeout, reactor).await;
+                    if let Err(error @ Error::ConnectionTimeout(_)) = &res {
+                        // The server may never answer, let the io loop try another socket
+                        handshake_error.set(error.clone());
+                        waker.wake();
+                    }
+                    res.map(|connection| (connection, expires_in))
+                }
+                Err(error) => {
+                    // Without a handshake, the server will eventually close the socket
+                    connection.report_error(error.clone());
+                    Err(error)
+                }
+            };
+            match res {
+                Ok((connection, expires_in)) => {
+                    secret_refresh.start(connection.downgrade(), expires_in);
+                    connection.restore_after_recovery().await
+                }
+                // The io loop will try again if it makes sense
+                Err(error) => warn!(%error, "Failed to reconnect"),
+            }
+        }));
+        Ok(())
+    }
+}
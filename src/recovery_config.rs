@@ -0,0 +1,50 @@
+use crate::backoff;
+use std::time::Duration;
+
+/// Configuration of the automatic connection recovery.
+///
+/// When set on [`ConnectionProperties`], a [`Connection`] which loses its socket (IO error,
+/// missed heartbeats) will try to reconnect to the server instead of failing. Once reconnected,
+/// the topology returned by [`Connection::topology`] is replayed: every [`Channel`] is reopened
+/// with the same id and every [`Consumer`] resumes consuming, so existing streams keep running.
+///
+/// Between two attempts, we wait for an exponentially growing delay, starting at `initial_delay`
+/// and multiplied by `multiplier` after each failure, up to `max_delay`.
+///
+/// [`ConnectionProperties`]: ./struct.ConnectionProperties.html
+/// [`Connection`]: ./struct.Connection.html
+/// [`Connection::topology`]: ./struct.Connection.html#method.topology
+/// [`Channel`]: ./struct.Channel.html
+/// [`Consumer`]: ./struct.Consumer.html
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecoveryConfig {
+    /// Delay before the first reconnection attempt
+    pub initial_delay: Duration,
+    /// Upper bound for the delay between two attempts
+    pub max_delay: Duration,
+    /// Factor applied to the delay after each failed attempt
+    pub multiplier: u32,
+    /// Give up after that many failed attempts (`None` means we never give up)
+    pub max_attempts: Option<usize>,
+}
+
+impl Default for RecoveryConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2,
+            max_attempts: None,
+        }
+    }
+}
+
+impl RecoveryConfig {
+    pub(crate) fn delay(&self, attempt: usize) -> Duration {
+        backoff::delay(self.initial_delay, self.max_delay, self.multiplier, attempt)
+    }
+
+    pub(crate) fn can_retry(&self, attempt: usize) -> bool {
+        !matches!(self.max_attempts, Some(max) if attempt >= max)
+    }
+}
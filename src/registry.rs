@@ -26,6 +26,10 @@ impl Registry {
             .collect()
     }
 
+    pub(crate) fn queue(&self, name: &str) -> Option<QueueDefinitionInternal> {
+        self.0.lock().queues.get(name).cloned()
+    }
+
     pub(crate) fn register_exchange(
         &self,
         name: ShortString,
@@ -0,0 +1,231 @@
+use crate::{
+    backoff,
+    in_flight::{InFlight, Permit},
+    options::{BasicPublishOptions, ConfirmSelectOptions},
+    publisher_confirm::{Confirmation, PublisherConfirm},
+    BasicProperties, Channel, Promise, PromiseResolver, Result,
+};
+use bytes::Bytes;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tracing::{trace, warn};
+
+/// How a [`ReliablePublisher`] republishes the messages which weren't acked by the server, or
+/// how a [`Consumer`] resubscribes once the server cancelled it.
+///
+/// Between two attempts, we wait for an exponentially growing delay, starting at `initial_delay`
+/// and multiplied by `multiplier` after each failure, up to `max_delay`.
+///
+/// [`ReliablePublisher`]: ./struct.ReliablePublisher.html
+/// [`Consumer`]: ./struct.Consumer.html#method.set_auto_resubscribe
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// How many times we retry before giving up
+    pub max_retries: usize,
+    /// Delay before the first retry
+    pub initial_delay: Duration,
+    /// Upper bound for the delay between two attempts
+    pub max_delay: Duration,
+    /// Factor applied to the delay after each failed attempt
+    pub multiplier: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            multiplier: 2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub(crate) fn delay(&self, retry: usize) -> Duration {
+        backoff::delay(self.initial_delay, self.max_delay, self.multiplier, retry)
+    }
+}
+
+/// The final outcome of a message published through a [`ReliablePublisher`]
+///
+/// [`ReliablePublisher`]: ./struct.ReliablePublisher.html
+#[derive(Clone, Debug, PartialEq)]
+pub struct PublishOutcome {
+    /// The last confirmation received from the server.
+    ///
+    /// This is a `Nack` if the server still refused the message after the last retry.
+    pub confirmation: Confirmation,
+    /// How many times the message got published
+    pub attempts: usize,
+}
+
+/// A publisher built on top of publisher confirms which doesn't lose messages.
+///
+/// Each message is kept until the server acks it. Nacked messages, and messages dropped because
+/// of a channel error, get republished according to the [`RetryPolicy`]. At most
+/// `max_in_flight` messages can be waiting for their confirmation at the same time: publishing
+/// waits for a slot once this limit is reached.
+///
+/// [`RetryPolicy`]: ./struct.RetryPolicy.html
+#[derive(Clone)]
+pub struct ReliablePublisher {
+    channel: Channel,
+    retry_policy: RetryPolicy,
+    in_flight: InFlight,
+}
+
+impl ReliablePublisher {
+    /// Create a new publisher on `channel`, enabling publisher confirms on it if needed
+    pub async fn new(
+        channel: &Channel,
+        max_in_flight: usize,
+        retry_policy: RetryPolicy,
+    ) -> Result<Self> {
+        if !channel.status().confirm() {
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+        }
+        Ok(Self {
+            channel: channel.clone(),
+            retry_policy,
+            in_flight: InFlight::new(max_in_flight),
+        })
+    }
+
+    /// The number of messages currently waiting for their final outcome
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Publish a message, waiting for a slot if too many messages are in flight.
+    ///
+    /// The returned [`PublishHandle`] resolves once the message got acked, or once we gave up on
+    /// republishing it.
+    ///
+    /// [`PublishHandle`]: ./struct.PublishHandle.html
+    pub async fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: impl Into<Bytes>,
+        properties: BasicProperties,
+    ) -> Result<PublishHandle> {
+        // Our slot gets freed if we're dropped before handing the message over to the task
+        let permit = self.in_flight.acquire().await;
+        let message = Message {
+            exchange: exchange.into(),
+            routing_key: routing_key.into(),
+            options,
+            payload: payload.into(),
+            properties,
+        };
+        // Send the first attempt right away to preserve the publishing order
+        let first_attempt = message.publish(&self.channel).await;
+        let (promise, resolver) = Promise::new();
+        let publisher = self.clone();
+        self.channel.executor().spawn(Box::pin(async move {
+            publisher
+                .complete(message, first_attempt, resolver, permit)
+                .await;
+        }));
+        Ok(PublishHandle(promise))
+    }
+
+    async fn complete(
+        &self,
+        message: Message,
+        first_attempt: Result<PublisherConfirm>,
+        resolver: PromiseResolver<PublishOutcome>,
+        permit: Permit,
+    ) {
+        let mut attempts = 1;
+        let mut res = match first_attempt {
+            Ok(confirm) => confirm.await,
+            Err(error) => Err(error),
+        };
+        loop {
+            match res {
+                Ok(ref confirmation) if !confirmation.is_nack() => break,
+                _ if attempts > self.retry_policy.max_retries => break,
+                Ok(_) => trace!(attempts, "Message nacked, republishing"),
+                Err(ref error) => warn!(attempts, %error, "Message dropped, republishing"),
+            }
+            self.channel
+                .reactor()
+                .sleep(self.retry_policy.delay(attempts - 1))
+                .await;
+            attempts += 1;
+            res = match message.publish(&self.channel).await {
+                Ok(confirm) => confirm.await,
+                Err(error) => Err(error),
+            };
+        }
+        // Free our slot before handing out the outcome
+        drop(permit);
+        resolver.swear(res.map(|confirmation| PublishOutcome {
+            confirmation,
+            attempts,
+        }));
+    }
+}
+
+impl fmt::Debug for ReliablePublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReliablePublisher")
+            .field("channel", &self.channel.id())
+            .field("retry_policy", &self.retry_policy)
+            .field("in_flight", &self.in_flight())
+            .finish()
+    }
+}
+
+/// The final outcome of a message published through a [`ReliablePublisher`].
+///
+/// This resolves to an error if the last attempt to publish the message failed.
+///
+/// [`ReliablePublisher`]: ./struct.ReliablePublisher.html
+pub struct PublishHandle(Promise<PublishOutcome>);
+
+impl fmt::Debug for PublishHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PublishHandle").finish()
+    }
+}
+
+impl Future for PublishHandle {
+    type Output = Result<PublishOutcome>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+struct Message {
+    exchange: String,
+    routing_key: String,
+    options: BasicPublishOptions,
+    payload: Bytes,
+    properties: BasicProperties,
+}
+
+impl Message {
+    async fn publish(&self, channel: &Channel) -> Result<PublisherConfirm> {
+        channel
+            .basic_publish(
+                &self.exchange,
+                &self.routing_key,
+                self.options,
+                self.payload.clone(),
+                self.properties.clone(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,357 @@
+use crate::{
+    message::Delivery,
+    options::{
+        BasicAckOptions, BasicPublishOptions, ExchangeDeclareOptions, QueueBindOptions,
+        QueueDeclareOptions,
+    },
+    types::{AMQPValue, FieldTable, ShortString},
+    Channel, ExchangeKind, Result,
+};
+use std::{convert::TryFrom, time::Duration};
+use tracing::trace;
+
+/// A "retry after 1s, 10s, 60s then park" topology for a work queue, built on dead-lettering.
+///
+/// For a work queue named `work`, [`declare`] creates:
+/// - a `work.retry` direct exchange,
+/// - one `work.retry.N` queue per delay, bound to this exchange, whose messages expire after
+///   their delay and then get dead-lettered back to `work` through the default exchange,
+/// - a `work.parking` queue, bound to this exchange, for the messages we gave up on.
+///
+/// Everything gets declared through the regular channel methods, so that it's part of the
+/// topology restored on recovery. Failed deliveries are then handled using
+/// [`Delivery::retry_later`] and [`Delivery::park`].
+///
+/// [`declare`]: #method.declare
+/// [`Delivery::retry_later`]: ./message/struct.Delivery.html#method.retry_later
+/// [`Delivery::park`]: ./message/struct.Delivery.html#method.park
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryTopology {
+    queue: ShortString,
+    delays: Vec<Duration>,
+}
+
+/// What [`Delivery::retry_later`] did with a message
+///
+/// [`Delivery::retry_later`]: ./message/struct.Delivery.html#method.retry_later
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryOutcome {
+    /// The message will be delivered again after `delay`, for its `attempt`th retry
+    Retried { attempt: usize, delay: Duration },
+    /// The message was retried too many times and got moved to the parking queue
+    Parked,
+}
+
+impl RetryTopology {
+    /// The retry topology of the work queue `queue`, retrying messages after each of `delays`
+    pub fn new(queue: &str, delays: Vec<Duration>) -> Self {
+        Self {
+            queue: queue.into(),
+            delays,
+        }
+    }
+
+    /// The name of the work queue
+    pub fn queue(&self) -> &ShortString {
+        &self.queue
+    }
+
+    /// The delay before each retry
+    pub fn delays(&self) -> &[Duration] {
+        &self.delays
+    }
+
+    /// The name of the exchange routing the messages to the retry and parking queues
+    pub fn retry_exchange(&self) -> String {
+        format!("{}.retry", self.queue)
+    }
+
+    /// The name of the queue holding the messages waiting for their `attempt`th retry, from 1
+    pub fn retry_queue(&self, attempt: usize) -> String {
+        format!("{}.retry.{}", self.queue, attempt)
+    }
+
+    /// The name of the queue holding the messages we gave up on
+    pub fn parking_queue(&self) -> String {
+        format!("{}.parking", self.queue)
+    }
+
+    /// Declare the retry exchange, the retry and parking queues, and their bindings.
+    ///
+    /// The work queue itself is left untouched and must be declared separately.
+    pub async fn declare(&self, channel: &Channel) -> Result<()> {
+        let exchange = self.retry_exchange();
+        let durable = QueueDeclareOptions {
+            durable: true,
+            ..QueueDeclareOptions::default()
+        };
+        channel
+            .exchange_declare(
+                &exchange,
+                ExchangeKind::Direct,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..ExchangeDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+        for (index, delay) in self.delays.iter().enumerate() {
+            let queue = self.retry_queue(index + 1);
+            let mut arguments = FieldTable::default();
+            arguments.insert(
+                "x-message-ttl".into(),
+                AMQPValue::LongLongInt(i64::try_from(delay.as_millis()).unwrap_or(i64::MAX)),
+            );
+            arguments.insert(
+                "x-dead-letter-exchange".into(),
+                AMQPValue::LongString("".into()),
+            );
+            arguments.insert(
+                "x-dead-letter-routing-key".into(),
+                AMQPValue::LongString(self.queue.as_str().into()),
+            );
+            channel.queue_declare(&queue, durable, arguments).await?;
+            self.bind(channel, &queue, &exchange).await?;
+        }
+        let parking = self.parking_queue();
+        channel
+            .queue_declare(&parking, durable, FieldTable::default())
+            .await?;
+        self.bind(channel, &parking, &exchange).await
+    }
+
+    async fn bind(&self, channel: &Channel, queue: &str, exchange: &str) -> Result<()> {
+        channel
+            .queue_bind(
+                queue,
+                exchange,
+                queue,
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+    }
+
+    /// How many times `delivery` already went through the retry queues, from its `x-death` header
+    pub fn attempts(&self, delivery: &Delivery) -> usize {
+        let retry_queues = (1..=self.delays.len())
+            .map(|attempt| self.retry_queue(attempt))
+            .collect::<Vec<_>>();
+        x_deaths(delivery)
+            .filter(|death| {
+                field_str(death, "reason") == Some("expired")
+                    && field_str(death, "queue")
+                        .map_or(false, |queue| retry_queues.iter().any(|q| q == queue))
+            })
+            .map(death_count)
+            .sum()
+    }
+
+    /// What to do with `delivery` next: retry it after the delay matching the number of retries
+    /// it already went through, or park it once all the delays were used
+    fn next_outcome(&self, delivery: &Delivery) -> RetryOutcome {
+        let attempt = self.attempts(delivery) + 1;
+        match self.delays.get(attempt - 1) {
+            Some(delay) => RetryOutcome::Retried {
+                attempt,
+                delay: *delay,
+            },
+            None => RetryOutcome::Parked,
+        }
+    }
+}
+
+impl Delivery {
+    /// Schedule this message for another attempt on the work queue of `topology`, after the delay
+    /// matching the number of retries it already went through, or park it once all the delays
+    /// were used.
+    ///
+    /// A copy of the message is published on `channel` before this delivery gets acked.
+    pub async fn retry_later(
+        &self,
+        channel: &Channel,
+        topology: &RetryTopology,
+    ) -> Result<RetryOutcome> {
+        let outcome = topology.next_outcome(self);
+        match outcome {
+            RetryOutcome::Retried { attempt, delay } => {
+                trace!(queue=%topology.queue, attempt, ?delay, "Retrying message later");
+                self.move_to(channel, topology, &topology.retry_queue(attempt))
+                    .await?;
+            }
+            RetryOutcome::Parked => self.park(channel, topology).await?,
+        }
+        Ok(outcome)
+    }
+
+    /// Move this message to the parking queue of `topology`, acking this delivery.
+    pub async fn park(&self, channel: &Channel, topology: &RetryTopology) -> Result<()> {
+        trace!(queue=%topology.queue, "Parking message");
+        self.move_to(channel, topology, &topology.parking_queue())
+            .await
+    }
+
+    async fn move_to(
+        &self,
+        channel: &Channel,
+        topology: &RetryTopology,
+        queue: &str,
+    ) -> Result<()> {
+        // Wait for the confirmation if the channel is in confirm mode so that we don't lose the
+        // message
+        channel
+            .basic_publish(
+                &topology.retry_exchange(),
+                queue,
+                BasicPublishOptions::default(),
+                self.data.clone(),
+                self.properties.clone(),
+            )
+            .await?
+            .await?;
+        self.acker.ack(BasicAckOptions::default()).await
+    }
+}
+
+fn x_deaths(delivery: &Delivery) -> impl Iterator<Item = &FieldTable> {
+    let deaths = match delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get("x-death"))
+    {
+        Some(AMQPValue::FieldArray(deaths)) => deaths.as_slice(),
+        _ => &[],
+    };
+    deaths.iter().filter_map(|death| match death {
+        AMQPValue::FieldTable(death) => Some(death),
+        _ => None,
+    })
+}
+
+fn field_str<'a>(table: &'a FieldTable, key: &str) -> Option<&'a str> {
+    match table.inner().get(key)? {
+        AMQPValue::LongString(value) => std::str::from_utf8(value.as_bytes()).ok(),
+        AMQPValue::ShortString(value) => Some(value.as_str()),
+        _ => None,
+    }
+}
+
+fn death_count(death: &FieldTable) -> usize {
+    let count = match death.inner().get("count") {
+        Some(AMQPValue::LongLongInt(count)) => *count,
+        Some(AMQPValue::LongInt(count)) => (*count).into(),
+        Some(AMQPValue::LongUInt(count)) => (*count).into(),
+        _ => 1,
+    };
+    usize::try_from(count).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{types::FieldArray, BasicProperties};
+
+    fn topology() -> RetryTopology {
+        RetryTopology::new(
+            "work",
+            vec![
+                Duration::from_secs(1),
+                Duration::from_secs(10),
+                Duration::from_secs(60),
+            ],
+        )
+    }
+
+    fn death(queue: &str, reason: &str, count: AMQPValue) -> AMQPValue {
+        let mut death = FieldTable::default();
+        death.insert("queue".into(), AMQPValue::LongString(queue.into()));
+        death.insert("reason".into(), AMQPValue::LongString(reason.into()));
+        death.insert("count".into(), count);
+        AMQPValue::FieldTable(death)
+    }
+
+    fn delivery(deaths: Vec<AMQPValue>) -> Delivery {
+        let mut delivery = Delivery::new(1, 1, "".into(), "work".into(), false, None, None);
+        let mut headers = FieldTable::default();
+        headers.insert(
+            "x-death".into(),
+            AMQPValue::FieldArray(FieldArray::from(deaths)),
+        );
+        delivery.properties = BasicProperties::default().with_headers(headers);
+        delivery
+    }
+
+    #[test]
+    fn attempts() {
+        let topology = topology();
+        let no_header = Delivery::new(1, 1, "".into(), "work".into(), false, None, None);
+        assert_eq!(topology.attempts(&no_header), 0);
+        assert_eq!(topology.attempts(&delivery(Vec::new())), 0);
+
+        let delivery = delivery(vec![
+            death("work.retry.1", "expired", AMQPValue::LongLongInt(2)),
+            death("work.retry.2", "expired", AMQPValue::LongInt(1)),
+            // Count defaults to 1
+            AMQPValue::FieldTable({
+                let mut death = FieldTable::default();
+                death.insert(
+                    "queue".into(),
+                    AMQPValue::ShortString("work.retry.3".into()),
+                );
+                death.insert("reason".into(), AMQPValue::ShortString("expired".into()));
+                death
+            }),
+            // Not our retry queues, or not a retry
+            death("work", "rejected", AMQPValue::LongLongInt(5)),
+            death("other.retry.1", "expired", AMQPValue::LongLongInt(5)),
+            death("work.retry.4", "expired", AMQPValue::LongLongInt(5)),
+            death("work.retry.1", "maxlen", AMQPValue::LongLongInt(5)),
+            AMQPValue::LongLongInt(5),
+        ]);
+        assert_eq!(topology.attempts(&delivery), 4);
+    }
+
+    #[test]
+    fn next_outcome() {
+        let topology = topology();
+        let retried = |deaths| topology.next_outcome(&delivery(deaths));
+
+        assert_eq!(
+            retried(Vec::new()),
+            RetryOutcome::Retried {
+                attempt: 1,
+                delay: Duration::from_secs(1)
+            }
+        );
+        assert_eq!(
+            retried(vec![
+                death("work.retry.1", "expired", AMQPValue::LongLongInt(1)),
+                death("work", "rejected", AMQPValue::LongLongInt(2)),
+            ]),
+            RetryOutcome::Retried {
+                attempt: 2,
+                delay: Duration::from_secs(10)
+            }
+        );
+        assert_eq!(
+            retried(vec![
+                death("work.retry.1", "expired", AMQPValue::LongLongInt(1)),
+                death("work.retry.2", "expired", AMQPValue::LongLongInt(1)),
+                death("work.retry.3", "expired", AMQPValue::LongLongInt(1)),
+            ]),
+            RetryOutcome::Parked
+        );
+        // Messages retried more than there are delays, e.g. after removing one, get parked too
+        assert_eq!(
+            retried(vec![death(
+                "work.retry.1",
+                "expired",
+                AMQPValue::LongLongInt(7)
+            )]),
+            RetryOutcome::Parked
+        );
+    }
+}
@@ -2,18 +2,40 @@ use crate::{
     message::BasicReturnMessage, publisher_confirm::Confirmation, types::PayloadSize,
     BasicProperties, Promise,
 };
+use bytes::BytesMut;
+use executor_trait::FullExecutor;
+use flume::{r#async::RecvStream, Sender};
+use futures_lite::Stream;
 use parking_lot::Mutex;
-use std::{collections::VecDeque, fmt, sync::Arc};
+use std::{
+    collections::VecDeque,
+    fmt,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
 use tracing::{trace, warn};
 
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub(crate) struct ReturnedMessages {
     inner: Arc<Mutex<Inner>>,
+    listeners: Arc<Mutex<Listeners>>,
+    executor: Arc<dyn FullExecutor + Send + Sync>,
 }
 
 impl ReturnedMessages {
+    pub(crate) fn new(executor: Arc<dyn FullExecutor + Send + Sync>) -> Self {
+        Self {
+            inner: Arc::default(),
+            listeners: Arc::default(),
+            executor,
+        }
+    }
+
     pub(crate) fn start_new_delivery(&self, message: BasicReturnMessage) {
-        self.inner.lock().current_message = Some(message);
+        let mut inner = self.inner.lock();
+        inner.current_message = Some(message);
+        inner.current_payload.clear();
     }
 
     pub(crate) fn handle_content_header_frame(
@@ -22,9 +44,14 @@ impl ReturnedMessages {
         properties: BasicProperties,
         confirm_mode: bool,
     ) {
-        self.inner
-            .lock()
-            .handle_content_header_frame(size, properties, confirm_mode);
+        let listening = self.listening();
+        let message = self.inner.lock().handle_content_header_frame(
+            size,
+            properties,
+            confirm_mode,
+            listening,
+        );
+        self.notify(message);
     }
 
     pub(crate) fn handle_body_frame(
@@ -33,9 +60,47 @@ impl ReturnedMessages {
         payload: Vec<u8>,
         confirm_mode: bool,
     ) {
-        self.inner
-            .lock()
-            .handle_body_frame(remaining_size, payload, confirm_mode);
+        let listening = self.listening();
+        let message =
+            self.inner
+                .lock()
+                .handle_body_frame(remaining_size, payload, confirm_mode, listening);
+        self.notify(message);
+    }
+
+    pub(crate) fn set_handler<F: FnMut(BasicReturnMessage) + Send + 'static>(
+        &self,
+        mut handler: F,
+    ) {
+        // Run the handler on the executor, in order, rather than on the io loop thread.
+        // The previous handler stops once it handled the messages it already got.
+        let (sender, receiver) = flume::unbounded();
+        self.listeners.lock().handler = Some(sender);
+        self.executor.spawn(Box::pin(async move {
+            while let Ok(message) = receiver.recv_async().await {
+                handler(message);
+            }
+        }));
+    }
+
+    pub(crate) fn subscribe(&self) -> ReturnedMessageStream {
+        let (sender, receiver) = flume::unbounded();
+        self.listeners.lock().streams.push(sender);
+        ReturnedMessageStream(receiver.into_stream())
+    }
+
+    fn listening(&self) -> bool {
+        let mut listeners = self.listeners.lock();
+        // Forget about the subscribers which dropped their stream
+        listeners.streams.retain(|stream| !stream.is_disconnected());
+        listeners.handler.is_some() || !listeners.streams.is_empty()
+    }
+
+    // Called outside of the lock on inner so that the handler can use the channel
+    fn notify(&self, message: Option<BasicReturnMessage>) {
+        if let Some(message) = message {
+            self.listeners.lock().notify(message);
+        }
     }
 
     pub(crate) fn drain(&self) -> Vec<BasicReturnMessage> {
@@ -67,6 +132,7 @@ impl fmt::Debug for ReturnedMessages {
 #[derive(Default)]
 pub struct Inner {
     current_message: Option<BasicReturnMessage>,
+    current_payload: BytesMut,
     non_confirm_messages: Vec<BasicReturnMessage>,
     waiting_messages: VecDeque<BasicReturnMessage>,
     messages: Vec<BasicReturnMessage>,
@@ -79,12 +145,15 @@ impl Inner {
         size: PayloadSize,
         properties: BasicProperties,
         confirm_mode: bool,
-    ) {
+        listening: bool,
+    ) -> Option<BasicReturnMessage> {
         if let Some(message) = self.current_message.as_mut() {
             message.properties = properties;
         }
         if size == 0 {
-            self.new_delivery_complete(confirm_mode);
+            self.new_delivery_complete(confirm_mode, listening)
+        } else {
+            None
         }
     }
 
@@ -93,24 +162,39 @@ impl Inner {
         remaining_size: PayloadSize,
         payload: Vec<u8>,
         confirm_mode: bool,
-    ) {
+        listening: bool,
+    ) -> Option<BasicReturnMessage> {
         if let Some(message) = self.current_message.as_mut() {
-            message.receive_content(payload);
+            message.receive_content(payload, remaining_size, &mut self.current_payload);
         }
         if remaining_size == 0 {
-            self.new_delivery_complete(confirm_mode);
+            self.new_delivery_complete(confirm_mode, listening)
+        } else {
+            None
         }
     }
 
-    fn new_delivery_complete(&mut self, confirm_mode: bool) {
-        if let Some(message) = self.current_message.take() {
-            warn!(?message, "Server returned us a message");
-            if confirm_mode {
-                self.waiting_messages.push_back(message);
+    // Returns the message if it needs to be passed to the listeners
+    fn new_delivery_complete(
+        &mut self,
+        confirm_mode: bool,
+        listening: bool,
+    ) -> Option<BasicReturnMessage> {
+        let message = self.current_message.take()?;
+        warn!(?message, "Server returned us a message");
+        if confirm_mode {
+            if listening {
+                self.waiting_messages.push_back(message.clone());
             } else {
-                self.non_confirm_messages.push(message);
+                self.waiting_messages.push_back(message);
+                return None;
             }
+        } else if !listening {
+            // Keep it for wait_for_confirms as nobody is listening
+            self.non_confirm_messages.push(message);
+            return None;
         }
+        Some(message)
     }
 
     fn register_dropped_confirm(&mut self, promise: Promise<Confirmation>) {
@@ -162,3 +246,110 @@ impl Inner {
         messages
     }
 }
+
+#[derive(Default)]
+struct Listeners {
+    handler: Option<Sender<BasicReturnMessage>>,
+    streams: Vec<Sender<BasicReturnMessage>>,
+}
+
+impl Listeners {
+    fn notify(&mut self, message: BasicReturnMessage) {
+        // Forget about the subscribers which dropped their stream
+        self.streams
+            .retain(|stream| stream.send(message.clone()).is_ok());
+        if let Some(handler) = self.handler.as_ref() {
+            let _ = handler.send(message);
+        }
+    }
+}
+
+/// A Stream of the messages returned by the server on a [`Channel`]
+///
+/// See [`Channel::returned_messages`].
+///
+/// [`Channel`]: ./struct.Channel.html
+/// [`Channel::returned_messages`]: ./struct.Channel.html#method.returned_messages
+pub struct ReturnedMessageStream(RecvStream<'static, BasicReturnMessage>);
+
+impl fmt::Debug for ReturnedMessageStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReturnedMessageStream").finish()
+    }
+}
+
+impl Stream for ReturnedMessageStream {
+    type Item = BasicReturnMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures_lite::{future, StreamExt};
+    use std::time::Duration;
+
+    fn returned_messages() -> ReturnedMessages {
+        ReturnedMessages::new(Arc::new(async_global_executor_trait::AsyncGlobalExecutor))
+    }
+
+    fn return_message(returned_messages: &ReturnedMessages, routing_key: &str) {
+        returned_messages.start_new_delivery(BasicReturnMessage::new(
+            "".into(),
+            routing_key.into(),
+            312,
+            "NO_ROUTE".into(),
+        ));
+        returned_messages.handle_content_header_frame(0, BasicProperties::default(), false);
+    }
+
+    #[test]
+    fn handler() {
+        let returned = returned_messages();
+        let (sender, receiver) = flume::unbounded();
+        let listeners = returned.clone();
+        returned.set_handler(move |message| {
+            // The handler doesn't run with the listeners locked
+            assert!(listeners.listening());
+            sender.send(message.routing_key.to_string()).unwrap();
+        });
+
+        return_message(&returned, "first");
+        return_message(&returned, "second");
+        let timeout = Duration::from_secs(5);
+        assert_eq!(receiver.recv_timeout(timeout).unwrap(), "first");
+        assert_eq!(receiver.recv_timeout(timeout).unwrap(), "second");
+        assert!(returned.drain().is_empty());
+    }
+
+    #[test]
+    fn stream() {
+        let returned = returned_messages();
+        let mut stream = returned.subscribe();
+
+        return_message(&returned, "first");
+        return_message(&returned, "second");
+        let mut next =
+            || future::block_on(stream.next()).map(|message| message.routing_key.to_string());
+        assert_eq!(next(), Some("first".to_string()));
+        assert_eq!(next(), Some("second".to_string()));
+        assert!(returned.drain().is_empty());
+
+        // Without any listener, the messages are kept for wait_for_confirms
+        drop(stream);
+        assert!(!returned.listening());
+        return_message(&returned, "third");
+        assert_eq!(
+            returned
+                .drain()
+                .into_iter()
+                .map(|message| message.routing_key.to_string())
+                .collect::<Vec<_>>(),
+            vec!["third".to_string()]
+        );
+    }
+}
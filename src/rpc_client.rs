@@ -0,0 +1,294 @@
+use crate::{
+    consumer_status::ConsumerState,
+    message::{Delivery, DeliveryResult},
+    options::{BasicConsumeOptions, BasicPublishOptions},
+    types::{FieldTable, ShortString},
+    BasicProperties, Channel, Error, Promise, PromiseResolver, Result,
+};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    fmt,
+    hash::{BuildHasher, Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{trace, warn};
+
+/// The pseudo-queue used by RabbitMQ for direct reply-to
+const DIRECT_REPLY_TO: &str = "amq.rabbitmq.reply-to";
+
+/// A client sending requests and awaiting their replies, using RabbitMQ's
+/// [direct reply-to](https://www.rabbitmq.com/direct-reply-to.html).
+///
+/// Replies are consumed from `amq.rabbitmq.reply-to` in no-ack mode, on the channel the client
+/// got created with, and matched with their request using the `correlation_id` property.
+#[derive(Clone)]
+pub struct RpcClient {
+    channel: Channel,
+    consumer_tag: ShortString,
+    inner: Arc<Inner>,
+}
+
+impl RpcClient {
+    /// Create a new client on `channel`, subscribing to the direct reply-to pseudo-queue.
+    ///
+    /// Requests must be published on this same channel, so it shouldn't be used to consume
+    /// from `amq.rabbitmq.reply-to` elsewhere.
+    pub async fn new(channel: &Channel) -> Result<Self> {
+        let consumer = channel
+            .basic_consume(
+                DIRECT_REPLY_TO,
+                "",
+                BasicConsumeOptions {
+                    no_ack: true,
+                    ..BasicConsumeOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+        let mut hasher = RandomState::new().build_hasher();
+        channel.id().hash(&mut hasher);
+        let inner = Arc::new(Inner {
+            prefix: format!("{:016x}", hasher.finish()),
+            next_id: AtomicU64::new(0),
+            pending: Mutex::default(),
+        });
+        let replies = inner.clone();
+        consumer.set_delegate(move |delivery: DeliveryResult| {
+            let replies = replies.clone();
+            async move { replies.handle_reply(delivery) }
+        });
+        Ok(Self {
+            channel: channel.clone(),
+            consumer_tag: consumer.tag(),
+            inner,
+        })
+    }
+
+    /// Publish a request and wait for its reply for at most `timeout`.
+    ///
+    /// The `reply_to` and `correlation_id` properties get overridden.
+    pub async fn call(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        payload: impl Into<Bytes>,
+        properties: BasicProperties,
+        timeout: Duration,
+    ) -> Result<Delivery> {
+        let deadline = Instant::now() + timeout;
+        let correlation_id = self.inner.next_correlation_id();
+        let (promise, resolver) = Promise::new();
+        // Register the caller before publishing as the reply could come back before we're done.
+        // It gets unregistered once we return, or if we get dropped in the meantime.
+        let _registration = self.inner.register(correlation_id.clone(), resolver)?;
+        let properties = properties
+            .with_reply_to(DIRECT_REPLY_TO.into())
+            .with_correlation_id(correlation_id.clone());
+        self.channel
+            .basic_publish(
+                exchange,
+                routing_key,
+                BasicPublishOptions::default(),
+                payload,
+                properties,
+            )
+            .await?;
+        match self.channel.until(deadline, promise).await {
+            Some(reply) => reply,
+            None => {
+                trace!(%correlation_id, "RPC call timed out");
+                Err(Error::RpcTimeout(timeout))
+            }
+        }
+    }
+
+    /// The number of calls currently waiting for their reply
+    pub fn pending_calls(&self) -> usize {
+        self.inner.pending.lock().callers.len()
+    }
+}
+
+impl fmt::Debug for RpcClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcClient")
+            .field("channel", &self.channel.id())
+            .field("consumer_tag", &self.consumer_tag)
+            .field("pending_calls", &self.pending_calls())
+            .finish()
+    }
+}
+
+struct Inner {
+    prefix: String,
+    next_id: AtomicU64,
+    pending: Mutex<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    callers: HashMap<ShortString, PromiseResolver<Delivery>>,
+    cancelled: bool,
+}
+
+impl Inner {
+    fn next_correlation_id(&self) -> ShortString {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        format!("{}.{}", self.prefix, id).into()
+    }
+
+    fn register(
+        &self,
+        correlation_id: ShortString,
+        resolver: PromiseResolver<Delivery>,
+    ) -> Result<Registration<'_>> {
+        let mut pending = self.pending.lock();
+        if pending.cancelled {
+            return Err(Error::InvalidConsumerState(ConsumerState::Canceled));
+        }
+        pending.callers.insert(correlation_id.clone(), resolver);
+        Ok(Registration {
+            inner: self,
+            correlation_id,
+        })
+    }
+
+    fn handle_reply(&self, delivery: DeliveryResult) {
+        match delivery {
+            Ok(Some(delivery)) => {
+                let caller = delivery
+                    .properties
+                    .correlation_id()
+                    .as_ref()
+                    .and_then(|correlation_id| self.pending.lock().callers.remove(correlation_id));
+                match caller {
+                    Some(caller) => caller.swear(Ok(delivery)),
+                    None => warn!(
+                        correlation_id=?delivery.properties.correlation_id(),
+                        "Dropping RPC reply matching no pending call"
+                    ),
+                }
+            }
+            Ok(None) => {
+                // The reply consumer got cancelled, no reply will ever come back
+                let callers = {
+                    let mut pending = self.pending.lock();
+                    pending.cancelled = true;
+                    std::mem::take(&mut pending.callers)
+                };
+                let error = Error::InvalidConsumerState(ConsumerState::Canceled);
+                for caller in callers.into_iter().map(|(_, caller)| caller) {
+                    caller.swear(Err(error.clone()));
+                }
+            }
+            Err(error) => {
+                // Replies to the requests sent before the error are lost along with the channel
+                let callers = std::mem::take(&mut self.pending.lock().callers);
+                for caller in callers.into_iter().map(|(_, caller)| caller) {
+                    caller.swear(Err(error.clone()));
+                }
+            }
+        }
+    }
+}
+
+/// A pending call, unregistered when dropped
+struct Registration<'a> {
+    inner: &'a Inner,
+    correlation_id: ShortString,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        // This does nothing if the reply already came back
+        self.inner
+            .pending
+            .lock()
+            .callers
+            .remove(&self.correlation_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures_lite::future;
+
+    fn inner() -> Inner {
+        Inner {
+            prefix: "test".into(),
+            next_id: AtomicU64::new(0),
+            pending: Mutex::default(),
+        }
+    }
+
+    fn reply(correlation_id: &ShortString) -> DeliveryResult {
+        let mut delivery = Delivery::new(1, 1, "".into(), "reply".into(), false, None, None);
+        delivery.properties =
+            BasicProperties::default().with_correlation_id(correlation_id.clone());
+        Ok(Some(delivery))
+    }
+
+    #[test]
+    fn reply_resolves_call() {
+        let inner = inner();
+        let correlation_id = inner.next_correlation_id();
+        let (promise, resolver) = Promise::new();
+        let registration = inner.register(correlation_id.clone(), resolver).unwrap();
+
+        inner.handle_reply(reply(&correlation_id));
+        assert!(inner.pending.lock().callers.is_empty());
+        let delivery = future::block_on(promise).unwrap();
+        assert_eq!(
+            delivery.properties.correlation_id(),
+            &Some(correlation_id.clone())
+        );
+        drop(registration);
+
+        // A reply for a call which isn't pending anymore is dropped
+        inner.handle_reply(reply(&correlation_id));
+    }
+
+    #[test]
+    fn dropped_call_unregisters() {
+        let inner = inner();
+        let first = inner.next_correlation_id();
+        let second = inner.next_correlation_id();
+        assert_ne!(first, second);
+        let (_promise, resolver) = Promise::new();
+        let registration = inner.register(first.clone(), resolver).unwrap();
+        let (_promise, resolver) = Promise::new();
+        let _other = inner.register(second.clone(), resolver).unwrap();
+        assert_eq!(inner.pending.lock().callers.len(), 2);
+
+        // Like a call future being dropped while awaiting its reply
+        drop(registration);
+        let pending = inner.pending.lock();
+        assert_eq!(pending.callers.len(), 1);
+        assert!(pending.callers.contains_key(&second));
+    }
+
+    #[test]
+    fn cancelled_consumer_fails_calls() {
+        let inner = inner();
+        let (promise, resolver) = Promise::new();
+        let _registration = inner
+            .register(inner.next_correlation_id(), resolver)
+            .unwrap();
+
+        inner.handle_reply(Ok(None));
+        let cancelled = Error::InvalidConsumerState(ConsumerState::Canceled);
+        assert_eq!(future::block_on(promise).unwrap_err(), cancelled);
+        let (_promise, resolver) = Promise::new();
+        assert_eq!(
+            inner.register(inner.next_correlation_id(), resolver).err(),
+            Some(cancelled)
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{
+    message::Delivery,
+    options::{BasicConsumeOptions, BasicPublishOptions},
+    types::{FieldTable, ShortString},
+    BasicProperties, Channel, ConsumerRunner, FailurePolicy, Outcome, Result,
+};
+use bytes::Bytes;
+use std::{fmt, future::Future, sync::Arc};
+use tracing::{trace, warn};
+
+/// The server half of request/reply: runs a handler for each request and sends its result back.
+///
+/// For each request delivered on the queue it consumes, the handler gets called and the payload
+/// it returns gets published to the default exchange, with the `reply_to` of the request as routing key
+/// and the same `correlation_id`. The request is only acked once the reply got published, and
+/// confirmed by the server if the channel is in confirm mode.
+///
+/// - Requests without `reply_to` can't be answered: they're dead-lettered without calling the
+///   handler.
+/// - Requests for which the handler fails or panics are nacked without being requeued, so that
+///   they get dead-lettered if the queue is configured to.
+/// - Requests for which the reply couldn't be published are nacked and requeued.
+///
+/// This is a [`ConsumerRunner`] whose handler publishes the replies.
+///
+/// [`ConsumerRunner`]: ./struct.ConsumerRunner.html
+pub struct RpcServer {
+    channel: Channel,
+    runner: ConsumerRunner,
+}
+
+impl RpcServer {
+    /// Serve requests on `channel`, publishing the replies on it too.
+    ///
+    /// At most `max_concurrency` requests are handled at the same time, and the prefetch count
+    /// of the channel is set accordingly, as with [`ConsumerRunner`].
+    ///
+    /// [`ConsumerRunner`]: ./struct.ConsumerRunner.html
+    pub fn new(channel: &Channel, max_concurrency: usize) -> Self {
+        Self {
+            channel: channel.clone(),
+            runner: ConsumerRunner::new(channel, max_concurrency)
+                .with_failure_policy(FailurePolicy::DeadLetter),
+        }
+    }
+
+    /// Consume `queue` and handle requests until the consumer gets cancelled, then wait for the
+    /// ongoing ones.
+    ///
+    /// The consumer is never in no-ack mode, whatever `options` say. This returns an error if
+    /// the consumer failed.
+    pub async fn serve<H, F, P, E>(
+        self,
+        queue: &str,
+        consumer_tag: &str,
+        options: BasicConsumeOptions,
+        arguments: FieldTable,
+        handler: H,
+    ) -> Result<()>
+    where
+        H: Fn(Delivery) -> F + Send + Sync + 'static,
+        F: Future<Output = std::result::Result<P, E>> + Send + 'static,
+        P: Into<Bytes> + Send,
+        E: fmt::Display + Send,
+    {
+        let RpcServer { channel, runner } = self;
+        let handler = Arc::new(handler);
+        runner
+            .run(queue, consumer_tag, options, arguments, move |delivery| {
+                let channel = channel.clone();
+                let handler = handler.clone();
+                async move {
+                    let correlation_id = delivery.properties.correlation_id().clone();
+                    let reply_to = match delivery.properties.reply_to().clone() {
+                        Some(reply_to) => reply_to,
+                        None => {
+                            warn!(delivery_tag=%delivery.delivery_tag, "Dropping RPC request without reply_to");
+                            return Ok::<_, E>(Outcome::DeadLetter);
+                        }
+                    };
+                    let payload = handler(delivery).await?.into();
+                    Ok(reply(&channel, reply_to, correlation_id, payload).await)
+                }
+            })
+            .await
+    }
+}
+
+impl fmt::Debug for RpcServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcServer")
+            .field("runner", &self.runner)
+            .finish()
+    }
+}
+
+/// Publish the reply, telling what to do with the request
+async fn reply(
+    channel: &Channel,
+    reply_to: ShortString,
+    correlation_id: Option<ShortString>,
+    payload: Bytes,
+) -> Outcome {
+    let mut properties = BasicProperties::default();
+    if let Some(correlation_id) = correlation_id {
+        properties = properties.with_correlation_id(correlation_id);
+    }
+    let published = match channel
+        .basic_publish(
+            "",
+            reply_to.as_str(),
+            BasicPublishOptions::default(),
+            payload,
+            properties,
+        )
+        .await
+    {
+        Ok(confirm) => confirm.await.map(|confirmation| !confirmation.is_nack()),
+        Err(error) => Err(error),
+    };
+    match published {
+        Ok(true) => {
+            trace!(%reply_to, "RPC reply sent, acking the request");
+            Outcome::Ack
+        }
+        Ok(false) => {
+            warn!(%reply_to, "RPC reply nacked by the server, requeuing the request");
+            Outcome::Requeue
+        }
+        Err(error) => {
+            warn!(%reply_to, %error, "Failed to send RPC reply, requeuing the request");
+            Outcome::Requeue
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{
+    auth::{Credentials, SASLMechanism},
+    Error, Result,
+};
+
+/// A SASL mechanism used to authenticate against the server.
+///
+/// The builtin mechanisms from [`SASLMechanism`] implement this trait. Set a custom one using
+/// [`ConnectionProperties::with_sasl_mechanism`] to support other mechanisms such as SCRAM.
+/// The first configured mechanism offered by the server gets used.
+///
+/// [`SASLMechanism`]: ./auth/enum.SASLMechanism.html
+/// [`ConnectionProperties::with_sasl_mechanism`]: ./struct.ConnectionProperties.html#method.with_sasl_mechanism
+pub trait SaslMechanism: Send + Sync {
+    /// The name of the mechanism, matched against the ones offered in `connection.start` and
+    /// sent in `connection.start-ok`
+    fn name(&self) -> String;
+
+    /// Start a new authentication exchange.
+    ///
+    /// `server_mechanisms` are the mechanisms offered by the server in `connection.start`.
+    /// This is called once per connection attempt, so that the returned [`SaslSession`] can
+    /// hold the state of this specific exchange.
+    ///
+    /// [`SaslSession`]: ./trait.SaslSession.html
+    fn start(
+        &self,
+        server_mechanisms: &[&str],
+        credentials: &Credentials,
+    ) -> Result<Box<dyn SaslSession>>;
+}
+
+/// The state of an ongoing SASL authentication exchange
+pub trait SaslSession: Send {
+    /// The initial response, sent in `connection.start-ok`
+    fn initial_response(&mut self) -> Result<String>;
+
+    /// The answer to a `connection.secure` challenge, sent in `connection.secure-ok`.
+    ///
+    /// The server may send any number of challenges before `connection.tune`.
+    fn challenge(&mut self, challenge: &[u8]) -> Result<String>;
+}
+
+impl SaslMechanism for SASLMechanism {
+    fn name(&self) -> String {
+        self.to_string()
+    }
+
+    fn start(
+        &self,
+        _server_mechanisms: &[&str],
+        credentials: &Credentials,
+    ) -> Result<Box<dyn SaslSession>> {
+        Ok(Box::new(BuiltinSession {
+            mechanism: *self,
+            credentials: credentials.clone(),
+        }))
+    }
+}
+
+struct BuiltinSession {
+    mechanism: SASLMechanism,
+    credentials: Credentials,
+}
+
+impl SaslSession for BuiltinSession {
+    fn initial_response(&mut self) -> Result<String> {
+        Ok(self.credentials.sasl_auth_string(self.mechanism))
+    }
+
+    fn challenge(&mut self, _challenge: &[u8]) -> Result<String> {
+        match self.mechanism {
+            SASLMechanism::RabbitCrDemo => Ok(self.credentials.rabbit_cr_demo_answer()),
+            mechanism => Err(Error::SaslError(format!(
+                "unexpected challenge for the {} mechanism",
+                mechanism
+            ))),
+        }
+    }
+}
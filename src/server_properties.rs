@@ -0,0 +1,110 @@
+use crate::types::{AMQPValue, FieldTable, ShortString};
+
+/// The properties sent by the server in `connection.start`.
+///
+/// This tells which broker we're connected to and which protocol extensions it supports.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ServerProperties(FieldTable);
+
+impl ServerProperties {
+    /// The raw properties table
+    pub fn properties(&self) -> &FieldTable {
+        &self.0
+    }
+
+    /// The name of the broker, e.g. "RabbitMQ"
+    pub fn product(&self) -> Option<String> {
+        self.string("product")
+    }
+
+    /// The version of the broker
+    pub fn version(&self) -> Option<String> {
+        self.string("version")
+    }
+
+    /// The platform the broker runs on
+    pub fn platform(&self) -> Option<String> {
+        self.string("platform")
+    }
+
+    /// The name of the cluster the broker is part of
+    pub fn cluster_name(&self) -> Option<String> {
+        self.string("cluster_name")
+    }
+
+    /// The capabilities table advertised by the broker
+    pub fn capabilities(&self) -> Option<&FieldTable> {
+        match self.0.inner().get(&ShortString::from("capabilities")) {
+            Some(AMQPValue::FieldTable(capabilities)) => Some(capabilities),
+            _ => None,
+        }
+    }
+
+    /// Whether the broker advertises the given capability
+    pub fn supports(&self, capability: &str) -> bool {
+        matches!(
+            self.capabilities()
+                .and_then(|capabilities| capabilities.inner().get(&ShortString::from(capability))),
+            Some(AMQPValue::Boolean(true))
+        )
+    }
+
+    /// Whether the broker supports publisher confirms (`confirm.select`)
+    pub fn supports_publisher_confirms(&self) -> bool {
+        self.supports("publisher_confirms")
+    }
+
+    /// Whether the broker supports binding exchanges to exchanges
+    pub fn supports_exchange_exchange_bindings(&self) -> bool {
+        self.supports("exchange_exchange_bindings")
+    }
+
+    /// Whether the broker supports `basic.nack`
+    pub fn supports_basic_nack(&self) -> bool {
+        self.supports("basic.nack")
+    }
+
+    /// Whether the broker notifies consumers when they get cancelled
+    pub fn supports_consumer_cancel_notify(&self) -> bool {
+        self.supports("consumer_cancel_notify")
+    }
+
+    /// Whether the broker notifies us when the connection gets blocked
+    pub fn supports_connection_blocked(&self) -> bool {
+        self.supports("connection.blocked")
+    }
+
+    /// Whether the broker supports consumer priorities
+    pub fn supports_consumer_priorities(&self) -> bool {
+        self.supports("consumer_priorities")
+    }
+
+    /// Whether the broker closes the connection with a specific error on authentication failure
+    pub fn supports_authentication_failure_close(&self) -> bool {
+        self.supports("authentication_failure_close")
+    }
+
+    /// Whether `basic.qos` can apply to each consumer instead of the whole channel
+    pub fn supports_per_consumer_qos(&self) -> bool {
+        self.supports("per_consumer_qos")
+    }
+
+    /// Whether the broker supports direct reply-to
+    pub fn supports_direct_reply_to(&self) -> bool {
+        self.supports("direct_reply_to")
+    }
+
+    fn string(&self, key: &str) -> Option<String> {
+        match self.0.inner().get(&ShortString::from(key)) {
+            Some(AMQPValue::LongString(value)) => Some(value.to_string()),
+            Some(AMQPValue::ShortString(value)) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl From<FieldTable> for ServerProperties {
+    fn from(properties: FieldTable) -> Self {
+        Self(properties)
+    }
+}
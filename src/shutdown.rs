@@ -0,0 +1,25 @@
+use crate::types::{ChannelId, DeliveryTag, ShortString};
+
+/// What a [`Connection::shutdown`] couldn't complete before its deadline.
+///
+/// [`Connection::shutdown`]: ./struct.Connection.html#method.shutdown
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShutdownReport {
+    /// The consumers we failed to cancel, by channel
+    pub uncancelled_consumers: Vec<(ChannelId, ShortString)>,
+    /// The deliveries which were neither acked, nacked nor rejected, by channel.
+    ///
+    /// The server requeues them once their channel is closed.
+    pub unacked_deliveries: Vec<(ChannelId, DeliveryTag)>,
+    /// The published messages which weren't confirmed by the server, by channel
+    pub unconfirmed_publishes: Vec<(ChannelId, DeliveryTag)>,
+}
+
+impl ShutdownReport {
+    /// Whether everything got cancelled, acked and confirmed before the deadline
+    pub fn is_complete(&self) -> bool {
+        self.uncancelled_consumers.is_empty()
+            && self.unacked_deliveries.is_empty()
+            && self.unconfirmed_publishes.is_empty()
+    }
+}
@@ -1,6 +1,6 @@
 use crate::Result;
 use flume::{Receiver, Sender};
-use std::task::Poll;
+use std::{task::Poll, time::Duration};
 use tracing::trace;
 
 pub(crate) struct SocketState {
@@ -47,6 +47,17 @@ impl SocketState {
         self.handle_event(self.events.recv().expect("waiting for socket event failed"))
     }
 
+    pub(crate) fn wait_timeout(&mut self, timeout: Duration) {
+        if let Ok(event) = self.events.recv_timeout(timeout) {
+            self.handle_event(event);
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.readable = true;
+        self.writable = true;
+    }
+
     pub(crate) fn handle(&self) -> SocketStateHandle {
         self.handle.clone()
     }
@@ -0,0 +1,269 @@
+use crate::{
+    acker::Acker, consumer::BodyReleaser, message::Delivery, types::PayloadSize, Consumer, Error,
+    Result,
+};
+use bytes::Bytes;
+use flume::{r#async::RecvStream, Receiver, Sender};
+use futures_lite::{io::AsyncRead, Stream};
+use std::{
+    fmt, io,
+    ops::Deref,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// A [`Consumer`] handing out its deliveries as soon as their content header got received,
+/// streaming their body afterwards.
+///
+/// This lets large payloads be written to disk or forwarded without holding them in memory.
+/// It is obtained using [`Channel::basic_consume_streaming`].
+///
+/// [`Consumer`]: ./struct.Consumer.html
+/// [`Channel::basic_consume_streaming`]: ./struct.Channel.html#method.basic_consume_streaming
+#[derive(Debug)]
+pub struct StreamingConsumer(Consumer);
+
+impl StreamingConsumer {
+    pub(crate) fn new(consumer: Consumer) -> Self {
+        Self(consumer)
+    }
+
+    /// The underlying consumer
+    pub fn consumer(&self) -> &Consumer {
+        &self.0
+    }
+}
+
+impl Stream for StreamingConsumer {
+    type Item = Result<StreamingDelivery>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_streaming(cx)
+    }
+}
+
+/// A delivery from a [`StreamingConsumer`]
+///
+/// [`StreamingConsumer`]: ./struct.StreamingConsumer.html
+#[derive(Debug)]
+pub struct StreamingDelivery {
+    /// The delivery, its `data` being left empty
+    pub delivery: Delivery,
+    /// The size of the body, as announced by its content header
+    pub body_size: PayloadSize,
+    /// The body, as it gets received
+    pub body: BodyStream,
+}
+
+impl Deref for StreamingDelivery {
+    type Target = Acker;
+
+    fn deref(&self) -> &Self::Target {
+        &self.delivery.acker
+    }
+}
+
+/// The body of a [`StreamingDelivery`], fed by its body frames as they get received.
+///
+/// It can be consumed either as a [`Stream`] of chunks or as an [`AsyncRead`]. If the channel
+/// goes away before the whole body got received, the stream yields an error and reading fails
+/// with [`io::ErrorKind::UnexpectedEof`].
+///
+/// The chunks which weren't read yet count in the consumer's [`BufferLimits`], reading from the
+/// connection pausing according to the [`BufferOverflowPolicy`] until the body gets drained.
+/// With [`BufferOverflowPolicy::FailConsumer`], there is no backpressure.
+///
+/// [`StreamingDelivery`]: ./struct.StreamingDelivery.html
+/// [`Stream`]: https://docs.rs/futures-core/*/futures_core/stream/trait.Stream.html
+/// [`AsyncRead`]: https://docs.rs/futures-io/*/futures_io/trait.AsyncRead.html
+/// [`io::ErrorKind::UnexpectedEof`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.UnexpectedEof
+/// [`BufferLimits`]: ./struct.BufferLimits.html
+/// [`BufferOverflowPolicy`]: ./enum.BufferOverflowPolicy.html
+/// [`BufferOverflowPolicy::FailConsumer`]: ./enum.BufferOverflowPolicy.html#variant.FailConsumer
+pub struct BodyStream {
+    chunks: Option<RecvStream<'static, Bytes>>,
+    // Kept to drain the chunks which won't be read when dropped
+    receiver: Option<Receiver<Bytes>>,
+    releaser: BodyReleaser,
+    remaining: PayloadSize,
+    current: Bytes,
+    truncated: bool,
+}
+
+impl BodyStream {
+    fn new(size: PayloadSize, releaser: BodyReleaser) -> (Sender<Bytes>, Self) {
+        let (sender, receiver) = flume::unbounded();
+        (
+            sender,
+            Self {
+                chunks: Some(receiver.clone().into_stream()),
+                receiver: Some(receiver),
+                releaser,
+                remaining: size,
+                current: Bytes::new(),
+                truncated: false,
+            },
+        )
+    }
+
+    /// How many bytes of the body weren't read yet
+    pub fn remaining(&self) -> PayloadSize {
+        self.remaining + self.current.len() as PayloadSize
+    }
+
+    fn poll_chunk(&mut self, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        let chunks = self.chunks.as_mut().expect("body stream polled after drop");
+        match Pin::new(chunks).poll_next(cx) {
+            Poll::Ready(Some(chunk)) => {
+                self.releaser.release(chunk.len());
+                self.remaining = self.remaining.saturating_sub(chunk.len() as PayloadSize);
+                Poll::Ready(Some(chunk))
+            }
+            Poll::Ready(None) => {
+                if self.remaining > 0 {
+                    self.truncated = true;
+                    self.remaining = 0;
+                }
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "the channel went away before the whole body got received",
+    )
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyStream")
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+impl Drop for BodyStream {
+    fn drop(&mut self) {
+        let chunks = self.chunks.take();
+        let receiver = self.receiver.take();
+        // Forget about the chunks which won't be read, before any new one can be accounted for
+        self.releaser.release_with(move || {
+            drop(chunks);
+            let size = receiver.as_ref().map_or(0, |receiver| {
+                receiver.drain().map(|chunk| chunk.len()).sum()
+            });
+            drop(receiver);
+            size
+        });
+    }
+}
+
+impl Stream for BodyStream {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if !self.current.is_empty() {
+            return Poll::Ready(Some(Ok(std::mem::take(&mut self.current))));
+        }
+        match self.poll_chunk(cx) {
+            Poll::Ready(Some(chunk)) => Poll::Ready(Some(Ok(chunk))),
+            Poll::Ready(None) if self.truncated => {
+                self.truncated = false;
+                Poll::Ready(Some(Err(Error::IOError(Arc::new(unexpected_eof())))))
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncRead for BodyStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        while self.current.is_empty() {
+            match self.poll_chunk(cx) {
+                Poll::Ready(Some(chunk)) => self.current = chunk,
+                Poll::Ready(None) if self.truncated => return Poll::Ready(Err(unexpected_eof())),
+                Poll::Ready(None) => return Poll::Ready(Ok(0)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let len = buf.len().min(self.current.len());
+        buf[..len].copy_from_slice(&self.current.split_to(len));
+        Poll::Ready(Ok(len))
+    }
+}
+
+pub(crate) type StreamingDeliveryResult = Result<Option<StreamingDelivery>>;
+
+/// The streaming state of a consumer
+pub(crate) struct Streaming {
+    deliveries_in: Sender<StreamingDeliveryResult>,
+    deliveries_out: Receiver<StreamingDeliveryResult>,
+    body: Option<Sender<Bytes>>,
+}
+
+impl Default for Streaming {
+    fn default() -> Self {
+        let (deliveries_in, deliveries_out) = flume::unbounded();
+        Self {
+            deliveries_in,
+            deliveries_out,
+            body: None,
+        }
+    }
+}
+
+impl Streaming {
+    pub(crate) fn start_body(
+        &mut self,
+        delivery: Delivery,
+        body_size: PayloadSize,
+        releaser: BodyReleaser,
+    ) {
+        let (sender, body) = BodyStream::new(body_size, releaser);
+        // An empty body is already complete
+        self.body = if body_size > 0 { Some(sender) } else { None };
+        self.send(Ok(Some(StreamingDelivery {
+            delivery,
+            body_size,
+            body,
+        })));
+    }
+
+    /// Feed the current body, returns whether the chunk is waiting to be read
+    pub(crate) fn receive_body(&mut self, chunk: Vec<u8>, remaining_size: PayloadSize) -> bool {
+        // Ignore the chunks of a body which is no longer being read
+        let sent = self
+            .body
+            .as_ref()
+            .map_or(false, |body| body.send(chunk.into()).is_ok());
+        if remaining_size == 0 {
+            self.body = None;
+        }
+        sent
+    }
+
+    /// Stop feeding the current body, which didn't get completely received
+    pub(crate) fn interrupt(&mut self) {
+        self.body = None;
+    }
+
+    pub(crate) fn send(&self, delivery: StreamingDeliveryResult) {
+        self.deliveries_in
+            .send(delivery)
+            .expect("failed to send delivery to streaming consumer");
+    }
+
+    pub(crate) fn next_delivery(&self) -> Option<StreamingDeliveryResult> {
+        self.deliveries_out.try_recv().ok()
+    }
+}
@@ -0,0 +1,63 @@
+use crate::{Channel, Error};
+use futures_lite::FutureExt;
+use std::{
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+};
+use tracing::{trace, warn};
+
+impl Channel {
+    /// Run `body` in a transaction, committing it if `body` succeeds and rolling it back if it
+    /// fails or panics.
+    ///
+    /// The channel is put in transactional mode the first time, using `tx.select`. It stays in
+    /// this mode afterwards, so everything published or acked on this channel outside of this
+    /// helper will only take effect on the next commit. As a channel can't use both
+    /// transactions and publisher confirms, this fails with [`Error::ChannelInConfirmMode`] if
+    /// the channel is in confirm mode.
+    ///
+    /// `body` gets a handle to this channel, which it uses to publish and ack the messages
+    /// belonging to the transaction. Don't run several transactions on the same channel
+    /// concurrently: they would be mixed together.
+    ///
+    /// [`Error::ChannelInConfirmMode`]: ./enum.Error.html#variant.ChannelInConfirmMode
+    pub async fn transaction<F, Fut, T, E>(&self, body: F) -> Result<T, E>
+    where
+        F: FnOnce(Channel) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: From<Error>,
+    {
+        if self.status().confirm() {
+            return Err(Error::ChannelInConfirmMode(self.id()).into());
+        }
+        if !self.status().transactional() {
+            self.tx_select().await?;
+        }
+        let channel = self.clone();
+        match AssertUnwindSafe(async move { body(channel).await })
+            .catch_unwind()
+            .await
+        {
+            Ok(Ok(value)) => {
+                trace!(channel=%self.id(), "Committing transaction");
+                self.tx_commit().await?;
+                Ok(value)
+            }
+            Ok(Err(error)) => {
+                self.rollback().await;
+                Err(error)
+            }
+            Err(payload) => {
+                self.rollback().await;
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    async fn rollback(&self) {
+        trace!(channel=%self.id(), "Rolling transaction back");
+        if let Err(error) = self.tx_rollback().await {
+            warn!(channel=%self.id(), %error, "Failed to roll transaction back");
+        }
+    }
+}
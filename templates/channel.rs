@@ -117,7 +117,7 @@ impl Channel {
     }));
 
     {{#if method.metadata.carry_headers ~}}
-    self.send_method_frame_with_body(method, payload, properties, start_hook_res).await
+    self.send_method_frame_with_body(method, payload.into(), properties, start_hook_res).await
     {{else}}
     let (promise, send_resolver) = Promise::new();
     if level_enabled!(Level::TRACE) {